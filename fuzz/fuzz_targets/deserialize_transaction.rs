@@ -1,4 +1,5 @@
 extern crate bitcoin;
+use bitcoin::blockdata::witness::Witness;
 type BResult = Result<bitcoin::blockdata::transaction::Transaction, bitcoin::util::Error>;
 fn do_test(data: &[u8]) {
     let tx_result: BResult = bitcoin::network::serialize::deserialize(data);
@@ -8,7 +9,7 @@ fn do_test(data: &[u8]) {
             let len = bitcoin::network::serialize::serialize(&tx).unwrap().len() as u64;
             let calculated_weight = tx.get_weight();
             for input in &mut tx.input {
-                input.witness = vec![];
+                input.witness = Witness::new();
             }
             let no_witness_len = bitcoin::network::serialize::serialize(&tx).unwrap().len() as u64;
             assert_eq!(no_witness_len * 3 + len, calculated_weight);
@@ -24,6 +24,7 @@ use std::default::Default;
 use blockdata::opcodes;
 use blockdata::script;
 use blockdata::transaction::{Transaction, TxOut, TxIn};
+use blockdata::witness::Witness;
 use blockdata::block::{Block, BlockHeader};
 use network::constants::Network;
 use util::misc::hex_bytes;
@@ -34,6 +35,8 @@ use util::uint::Uint256;
 pub static MAX_SEQUENCE: u32 = 0xFFFFFFFF;
 /// How many satoshis are in "one bitcoin"
 pub static COIN_VALUE: u64 = 100_000_000;
+/// The maximum number of satoshis that can ever exist, on any network
+pub const MAX_MONEY: u64 = 21_000_000 * 100_000_000;
 /// How many seconds between blocks we expect on average
 pub static TARGET_BLOCK_SPACING: u32 = 600;
 /// How many blocks between diffchanges
@@ -50,7 +53,14 @@ pub fn max_target(_: Network) -> Uint256 {
 /// since keeping everything below this value should prevent overflows
 /// if you are doing anything remotely sane with monetary values).
 pub fn max_money(_: Network) -> u64 {
-    21_000_000 * COIN_VALUE
+    MAX_MONEY
+}
+
+/// Whether `value` lies within the range allowed for an output value or a sum
+/// of output values, i.e. at most `MAX_MONEY`
+#[inline]
+pub fn is_valid_money_range(value: u64) -> bool {
+    value <= MAX_MONEY
 }
 
 /// Constructs and returns the coinbase (and only) transaction of the Bitcoin genesis block
@@ -73,7 +83,7 @@ fn bitcoin_genesis_tx() -> Transaction {
         prev_index: 0xFFFFFFFF,
         script_sig: in_script,
         sequence: MAX_SEQUENCE,
-        witness: vec![],
+        witness: Witness::new(),
     });
 
     // Outputs
@@ -132,7 +142,7 @@ mod test {
     use network::constants::Network;
     use network::serialize::{BitcoinHash, serialize};
     use blockdata::constants::{genesis_block, bitcoin_genesis_tx};
-    use blockdata::constants::{MAX_SEQUENCE, COIN_VALUE};
+    use blockdata::constants::{MAX_SEQUENCE, COIN_VALUE, MAX_MONEY, is_valid_money_range, max_money};
 
     #[test]
     fn bitcoin_genesis_first_transaction() {
@@ -184,5 +194,14 @@ mod test {
         assert_eq!(gen.header.bitcoin_hash().be_hex_string(),
                    "000000000933ea01ad0ee984209779baaec3ced90fa3f408719526f8d77f4943".to_string());
     }
-}
 
+    #[test]
+    fn money_range() {
+        assert_eq!(MAX_MONEY, 21_000_000 * COIN_VALUE);
+        assert_eq!(max_money(Network::Testnet), MAX_MONEY);
+        assert!(is_valid_money_range(0));
+        assert!(is_valid_money_range(MAX_MONEY));
+        assert!(!is_valid_money_range(MAX_MONEY + 1));
+        assert!(!is_valid_money_range(u64::max_value()));
+    }
+}
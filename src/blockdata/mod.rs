@@ -22,5 +22,6 @@ pub mod constants;
 pub mod opcodes;
 pub mod script;
 pub mod transaction;
+pub mod witness;
 pub mod block;
 
@@ -594,6 +594,20 @@ impl All {
         Class::Ordinary(unsafe { transmute(*self) })
       }
     }
+
+    /// Whether the opcode is one of those disabled by Satoshi in 2010 (OP_CAT,
+    /// OP_MUL, etc.). These are classified as `IllegalOp`, but unlike OP_VERIF
+    /// and OP_VERNOTIF they once had defined behaviour.
+    #[inline]
+    pub fn is_disabled(&self) -> bool {
+      *self == All::OP_CAT || *self == All::OP_SUBSTR ||
+      *self == All::OP_LEFT || *self == All::OP_RIGHT ||
+      *self == All::OP_INVERT || *self == All::OP_AND ||
+      *self == All::OP_OR || *self == All::OP_XOR ||
+      *self == All::OP_2MUL || *self == All::OP_2DIV ||
+      *self == All::OP_MUL || *self == All::OP_DIV || *self == All::OP_MOD ||
+      *self == All::OP_LSHIFT || *self == All::OP_RSHIFT
+    }
 }
 
 impl From<u8> for All {
@@ -700,4 +714,38 @@ ordinary_opcode! {
   OP_CHECKMULTISIG, OP_CHECKMULTISIGVERIFY
 }
 
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn classify_test() {
+    assert_eq!(All::OP_RETURN.classify(), Class::ReturnOp);
+    assert_eq!(All::OP_RETURN_186.classify(), Class::ReturnOp);
+    assert_eq!(All::OP_CAT.classify(), Class::IllegalOp);
+    assert_eq!(All::OP_VERIF.classify(), Class::IllegalOp);
+    assert_eq!(All::OP_NOP2.classify(), Class::NoOp);
+    assert_eq!(All::OP_PUSHNUM_NEG1.classify(), Class::PushNum(-1));
+    assert_eq!(All::OP_PUSHNUM_16.classify(), Class::PushNum(16));
+    assert_eq!(All::OP_PUSHBYTES_20.classify(), Class::PushBytes(20));
+    assert_eq!(All::OP_CHECKSIG.classify(), Class::Ordinary(Ordinary::OP_CHECKSIG));
+  }
 
+  #[test]
+  fn disabled_test() {
+    assert!(All::OP_CAT.is_disabled());
+    assert!(All::OP_MUL.is_disabled());
+    assert!(All::OP_RSHIFT.is_disabled());
+    // illegal, but never had any behaviour to disable
+    assert!(!All::OP_VERIF.is_disabled());
+    assert!(!All::OP_RETURN.is_disabled());
+    assert!(!All::OP_CHECKSIG.is_disabled());
+    // every disabled opcode is illegal
+    for b in 0..256u32 {
+      let op = All::from(b as u8);
+      if op.is_disabled() {
+        assert_eq!(op.classify(), Class::IllegalOp);
+      }
+    }
+  }
+}
@@ -25,31 +25,52 @@
 //!
 
 use std::default::Default;
-use std::{error, fmt};
+use std::{error, fmt, ops, str};
 
 use crypto::digest::Digest;
+use secp256k1::Secp256k1;
 use serde;
 
 use blockdata::opcodes;
-use network::encodable::{ConsensusDecodable, ConsensusEncodable};
+use network::encodable::{ConsensusDecodable, ConsensusEncodable, VarInt};
 use network::serialize::{SimpleDecoder, SimpleEncoder};
 use util::hash::Hash160;
+use util::key;
 #[cfg(feature="bitcoinconsensus")] use bitcoinconsensus;
 #[cfg(feature="bitcoinconsensus")] use std::convert;
 #[cfg(feature="bitcoinconsensus")] use util::hash::Sha256dHash;
+use blockdata::transaction::Transaction;
+#[cfg(feature="bitcoinconsensus")] use blockdata::transaction::TxOutRef;
+#[cfg(feature="bitcoinconsensus")] use network::serialize::deserialize;
+#[cfg(feature="bitcoinconsensus")] use secp256k1::Signature;
 
 #[cfg(feature="fuzztarget")]      use util::sha2::Sha256;
 #[cfg(not(feature="fuzztarget"))] use crypto::sha2::Sha256;
 
+/// Maximum size, in bytes, of a single element pushed onto the script stack
+pub const MAX_SCRIPT_ELEMENT_SIZE: usize = 520;
+
+/// The largest scriptPubKey which can be spent
+pub const MAX_SCRIPT_SIZE: usize = 10000;
+
 #[derive(Clone, PartialEq, Eq, Hash)]
 /// A Bitcoin script
 pub struct Script(Box<[u8]>);
 
 impl fmt::Debug for Script {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(f.write_str("Script("));
+        try!(self.fmt_asm(f));
+        f.write_str(")")
+    }
+}
+
+impl Script {
+    /// Writes the human-readable assembly form of the script, as used by
+    /// `asm` and the `Debug` impl
+    fn fmt_asm(&self, f: &mut fmt::Write) -> fmt::Result {
         let mut index = 0;
 
-        try!(f.write_str("Script("));
         while index < self.0.len() {
             let opcode = opcodes::All::from(self.0[index]);
             index += 1;
@@ -113,13 +134,21 @@ impl fmt::Debug for Script {
                 }
             }
         }
-        f.write_str(")")
+        Ok(())
+    }
+
+    /// Returns the human-readable assembly form of the script, e.g.
+    /// `OP_PUSHNUM_1 OP_PUSHBYTES_32 <hex>` for a v1 witness program
+    pub fn asm(&self) -> String {
+        let mut ret = String::new();
+        self.fmt_asm(&mut ret).unwrap();
+        ret
     }
 }
 
 impl fmt::Display for Script {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        fmt::Debug::fmt(self, f)
+        fmt::LowerHex::fmt(self, f)
     }
 }
 
@@ -141,11 +170,101 @@ impl fmt::UpperHex for Script {
     }
 }
 
+impl str::FromStr for Script {
+    type Err = Error;
+
+    /// Parses a script from its hex encoding
+    fn from_str(s: &str) -> Result<Script, Error> {
+        use serialize::hex::FromHex;
+
+        let raw_vec: Vec<u8> = try!(s.from_hex().map_err(|_| Error::InvalidHex));
+        Ok(Script::from(raw_vec))
+    }
+}
+
 #[derive(PartialEq, Eq, Debug, Clone)]
 /// An object which can be used to construct a script piece by piece
 pub struct Builder(Vec<u8>);
+
+/// A segwit version, which is always between 0 and 16
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct WitnessVersion(u8);
+
+impl WitnessVersion {
+    /// The version, or `None` if it is greater than 16
+    pub fn new(version: u8) -> Option<WitnessVersion> {
+        if version <= 16 { Some(WitnessVersion(version)) } else { None }
+    }
+
+    /// Version 0, of p2wpkh and p2wsh outputs
+    pub fn v0() -> WitnessVersion { WitnessVersion(0) }
+
+    /// The version as a number
+    pub fn to_u8(&self) -> u8 { self.0 }
+}
 display_from_debug!(Builder);
 
+/// A set of script verification flags, mirroring bitcoin-core's `SCRIPT_VERIFY_*`
+/// flags and bit values. Combine them with `|`.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct VerifyFlags(u32);
+
+/// No checks beyond the original script rules
+pub const VERIFY_NONE: VerifyFlags = VerifyFlags(0);
+/// Evaluate P2SH redeem scripts (BIP16)
+pub const VERIFY_P2SH: VerifyFlags = VerifyFlags(1 << 0);
+/// Require strict DER signatures (BIP66)
+pub const VERIFY_DERSIG: VerifyFlags = VerifyFlags(1 << 2);
+/// Require signatures to have an S value in the lower half of the curve order
+/// (policy)
+pub const VERIFY_LOW_S: VerifyFlags = VerifyFlags(1 << 3);
+/// Require the extra `OP_CHECKMULTISIG` argument to be empty (BIP147)
+pub const VERIFY_NULLDUMMY: VerifyFlags = VerifyFlags(1 << 4);
+/// Require exactly one element to remain on the stack (policy)
+pub const VERIFY_CLEANSTACK: VerifyFlags = VerifyFlags(1 << 8);
+/// Enforce `OP_CHECKLOCKTIMEVERIFY` (BIP65)
+pub const VERIFY_CHECKLOCKTIMEVERIFY: VerifyFlags = VerifyFlags(1 << 9);
+/// Enforce `OP_CHECKSEQUENCEVERIFY` (BIP112)
+pub const VERIFY_CHECKSEQUENCEVERIFY: VerifyFlags = VerifyFlags(1 << 10);
+/// Evaluate witness programs (BIP141)
+pub const VERIFY_WITNESS: VerifyFlags = VerifyFlags(1 << 11);
+/// Evaluate taproot spends (BIP341 and BIP342)
+pub const VERIFY_TAPROOT: VerifyFlags = VerifyFlags(1 << 17);
+/// The consensus rules enforced by `Script::verify`: every soft fork up to and
+/// including segwit. Taproot is not included as libbitcoinconsensus cannot
+/// check it.
+pub const VERIFY_CONSENSUS: VerifyFlags = VerifyFlags(1 << 0 | 1 << 2 | 1 << 4 | 1 << 9 | 1 << 10 | 1 << 11);
+
+impl VerifyFlags {
+    /// Creates a set of flags from bitcoin-core's integer representation
+    pub fn from_bits(bits: u32) -> VerifyFlags { VerifyFlags(bits) }
+
+    /// The integer representation of the flags, as used by bitcoin-core
+    pub fn bits(&self) -> u32 { self.0 }
+
+    /// Whether every flag in `other` is also in this set
+    pub fn contains(&self, other: VerifyFlags) -> bool { self.0 & other.0 == other.0 }
+}
+
+impl ops::BitOr for VerifyFlags {
+    type Output = VerifyFlags;
+    fn bitor(self, other: VerifyFlags) -> VerifyFlags { VerifyFlags(self.0 | other.0) }
+}
+
+impl ops::BitAnd for VerifyFlags {
+    type Output = VerifyFlags;
+    fn bitand(self, other: VerifyFlags) -> VerifyFlags { VerifyFlags(self.0 & other.0) }
+}
+
+impl ops::Not for VerifyFlags {
+    type Output = VerifyFlags;
+    fn not(self) -> VerifyFlags { VerifyFlags(!self.0) }
+}
+
+impl Default for VerifyFlags {
+    fn default() -> VerifyFlags { VERIFY_CONSENSUS }
+}
+
 /// Ways that a script might fail. Not everything is split up as
 /// much as it could be; patches welcome if more detailed errors
 /// would help you.
@@ -155,6 +274,18 @@ pub enum Error {
     EarlyEndOfScript,
     /// Tried to read an array off the stack as a number when it was more than 4 bytes
     NumericOverflow,
+    /// A number was not minimally encoded where minimal encoding is required
+    NonMinimalNumber,
+    /// Tried to push an element larger than `MAX_SCRIPT_ELEMENT_SIZE` bytes
+    ElementTooLarge(usize),
+    /// Tried to parse a script from a string that is not valid hex
+    InvalidHex,
+    /// An `OP_CHECKLOCKTIMEVERIFY` was not satisfied by the transaction
+    UnsatisfiedLocktime,
+    /// An `OP_CHECKSEQUENCEVERIFY` was not satisfied by the transaction
+    UnsatisfiedSequence,
+    /// The transaction has no input at the given index
+    InputIndexOutOfRange(usize),
     #[cfg(feature="bitcoinconsensus")]
     /// Error validating the script with bitcoinconsensus library
     BitcoinConsensus(bitcoinconsensus::Error),
@@ -165,8 +296,17 @@ pub enum Error {
     /// The spent transaction does not have the referred output
     WrongSpentOutputIndex(usize),
     #[cfg(feature="bitcoinconsensus")]
+    /// Can not find the spent output
+    UnknownSpentOutput(TxOutRef),
+    #[cfg(feature="bitcoinconsensus")]
     /// Can not serialize the spending transaction
-    SerializationError
+    SerializationError,
+    #[cfg(feature="bitcoinconsensus")]
+    /// A signature has an S value in the upper half of the curve order
+    SigHighS,
+    #[cfg(feature="bitcoinconsensus")]
+    /// Verification was asked to enforce flags which it does not support
+    UnsupportedVerifyFlags(VerifyFlags)
 }
 
 impl fmt::Display for Error {
@@ -182,6 +322,12 @@ impl error::Error for Error {
         match *self {
             Error::EarlyEndOfScript => "unexpected end of script",
             Error::NumericOverflow => "numeric overflow (number on stack larger than 4 bytes)",
+            Error::NonMinimalNumber => "number is not minimally encoded",
+            Error::ElementTooLarge(_) => "pushed element larger than 520 bytes",
+            Error::InvalidHex => "script is not valid hex",
+            Error::UnsatisfiedLocktime => "OP_CHECKLOCKTIMEVERIFY not satisfied",
+            Error::UnsatisfiedSequence => "OP_CHECKSEQUENCEVERIFY not satisfied",
+            Error::InputIndexOutOfRange(_) => "input index out of range",
             #[cfg(feature="bitcoinconsensus")]
             Error::BitcoinConsensus(ref _n) => "bitcoinconsenus verification failed",
             #[cfg(feature="bitcoinconsensus")]
@@ -189,11 +335,51 @@ impl error::Error for Error {
             #[cfg(feature="bitcoinconsensus")]
             Error::WrongSpentOutputIndex(ref _ix) => "unknown output index {} referred in Transaction::verify()",
             #[cfg(feature="bitcoinconsensus")]
+            Error::UnknownSpentOutput(ref _outref) => "unknown spent output referred in Transaction::verify_with()",
+            #[cfg(feature="bitcoinconsensus")]
             Error::SerializationError => "can not serialize the spending transaction in Transaction::verify()",
+            #[cfg(feature="bitcoinconsensus")]
+            Error::SigHighS => "signature has a high S value",
+            #[cfg(feature="bitcoinconsensus")]
+            Error::UnsupportedVerifyFlags(_) => "unsupported script verification flags",
         }
     }
 }
 
+/// Checks that every signature pushed by the scriptSig or witness of input
+/// `index` of the serialized transaction `spending` has a low S value. As in
+/// bitcoin-core's `IsLowDERSignature`, the last byte of each push is taken as
+/// the sighash type, whatever its value, and the rest parsed as DER.
+#[cfg(feature="bitcoinconsensus")]
+fn check_low_s(index: usize, spending: &[u8]) -> Result<(), Error> {
+    let tx: Transaction = match deserialize(spending) {
+        Ok(tx) => tx,
+        Err(_) => return Err(Error::BitcoinConsensus(bitcoinconsensus::Error::ERR_TX_DESERIALIZE))
+    };
+    let input = match tx.input.get(index) {
+        Some(input) => input,
+        None => return Err(Error::BitcoinConsensus(bitcoinconsensus::Error::ERR_TX_INDEX))
+    };
+    let secp = Secp256k1::without_caps();
+    let pushes = input.script_sig.into_iter().filter_map(|instruction| match instruction {
+        Instruction::PushBytes(data) => Some(data),
+        _ => None
+    });
+    for data in pushes.chain(input.witness.iter()) {
+        if data.is_empty() {
+            continue;
+        }
+        if let Ok(sig) = Signature::from_der(&secp, &data[..data.len() - 1]) {
+            let mut normalized = sig;
+            normalized.normalize_s(&secp);
+            if normalized != sig {
+                return Err(Error::SigHighS);
+            }
+        }
+    }
+    Ok(())
+}
+
 #[cfg(feature="bitcoinconsensus")]
 impl convert::From<bitcoinconsensus::Error> for Error {
     fn from(err: bitcoinconsensus::Error) -> Error {
@@ -243,19 +429,73 @@ fn build_scriptint(n: i64) -> Vec<u8> {
 /// simply say, anything in excess of 32 bits is no longer a number.
 /// This is basically a ranged type implementation.
 pub fn read_scriptint(v: &[u8]) -> Result<i64, Error> {
+    read_scriptint_max(v, 4)
+}
+
+/// Like `read_scriptint` but allowing numbers of up to `max_len` bytes, as
+/// the timelock opcodes do
+fn read_scriptint_max(v: &[u8], max_len: usize) -> Result<i64, Error> {
     let len = v.len();
     if len == 0 { return Ok(0); }
-    if len > 4 { return Err(Error::NumericOverflow); }
+    if len > max_len { return Err(Error::NumericOverflow); }
 
     let (mut ret, sh) = v.iter()
                          .fold((0, 0), |(acc, sh), n| (acc + ((*n as i64) << sh), sh + 8));
     if v[len - 1] & 0x80 != 0 {
-        ret &= (1 << (sh - 1)) - 1;
+        // clear the sign bit
+        ret &= !(0x80 << (sh - 8));
         ret = -ret;
     }
     Ok(ret)
 }
 
+/// The size limit of `ScriptNum`s consumed by arithmetic opcodes; the
+/// timelock opcodes allow 5 bytes
+pub const SCRIPTNUM_DEFAULT_MAX_SIZE: usize = 4;
+
+/// A number as read from and written to the script stack, following the
+/// semantics of bitcoin-core's `CScriptNum`: little-endian sign-magnitude,
+/// with the sign in the high bit of the last byte and zero as the empty
+/// array.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+pub struct ScriptNum(i64);
+
+impl ScriptNum {
+    /// Wraps an integer
+    pub fn new(n: i64) -> ScriptNum { ScriptNum(n) }
+
+    /// The integer value
+    pub fn value(&self) -> i64 { self.0 }
+
+    /// Reads a number of at most `max_size` bytes. With `require_minimal`,
+    /// as under the MINIMALDATA rule, encodings with unnecessary trailing zero
+    /// bytes (including negative zero) are rejected.
+    ///
+    /// # Panics
+    /// Panics if `max_size` is greater than 8
+    pub fn from_slice(data: &[u8], require_minimal: bool, max_size: usize) -> Result<ScriptNum, Error> {
+        assert!(max_size <= 8);
+        if data.len() > max_size {
+            return Err(Error::NumericOverflow);
+        }
+        if require_minimal {
+            if let Some(&last) = data.last() {
+                // The last byte may only be zero, bar the sign bit, if the
+                // byte before it needs the high bit it would otherwise take
+                if last & 0x7f == 0 && (data.len() == 1 || data[data.len() - 2] & 0x80 == 0) {
+                    return Err(Error::NonMinimalNumber);
+                }
+            }
+        }
+        read_scriptint_max(data, max_size).map(ScriptNum)
+    }
+
+    /// Serializes the number minimally
+    pub fn to_vec(&self) -> Vec<u8> {
+        build_scriptint(self.0)
+    }
+}
+
 /// This is like "`read_scriptint` then map 0 to false and everything
 /// else as true", except that the overflow rules don't apply.
 #[inline]
@@ -291,6 +531,41 @@ impl Script {
     /// Convert the script into a byte vector
     pub fn into_vec(self) -> Vec<u8> { self.0.into_vec() }
 
+    /// Returns a normalized form of the script, for fingerprinting scripts
+    /// which differ only in padding: trailing `OP_NOP`s are dropped and data
+    /// pushes are re-encoded with the shortest push opcode. Scripts which fail
+    /// to parse are returned unchanged.
+    ///
+    /// This is NOT consensus-equivalent: non-minimal pushes fail under
+    /// MINIMALDATA, and the length of the script changes.
+    pub fn normalized(&self) -> Script {
+        let mut instructions = vec![];
+        for instruction in self {
+            if let Instruction::Error(_) = instruction {
+                return self.clone();
+            }
+            instructions.push(instruction);
+        }
+        while instructions.last() == Some(&Instruction::Op(opcodes::All::OP_NOP)) {
+            instructions.pop();
+        }
+
+        let mut builder = Builder::new();
+        for instruction in instructions {
+            builder = match instruction {
+                Instruction::PushBytes(data) => builder.push_slice(data),
+                Instruction::Op(op) => builder.push_opcode(op),
+                Instruction::Error(_) => unreachable!(),
+            };
+        }
+        builder.into_script()
+    }
+
+    /// Whether the two scripts are equal after `normalized`; see its caveats
+    pub fn semantic_eq(&self, other: &Script) -> bool {
+        self.normalized() == other.normalized()
+    }
+
     /// Compute the P2SH output corresponding to this redeem script
     pub fn to_p2sh(&self) -> Script {
         Builder::new().push_opcode(opcodes::All::OP_HASH160)
@@ -305,7 +580,7 @@ impl Script {
         let mut sha2 = Sha256::new();
         sha2.input(&self.0);
         sha2.result(&mut tmp);
-        Builder::new().push_int(0)
+        Builder::new().push_witness_version(WitnessVersion::v0())
                       .push_slice(&tmp)
                       .into_script()
     }
@@ -354,12 +629,246 @@ impl Script {
             self.0[1] == opcodes::All::OP_PUSHBYTES_20 as u8
     }
 
+    /// Returns the BIP143 scriptCode for signing with this script. For a v0
+    /// P2WPKH program this is the implied P2PKH script,
+    /// `OP_DUP OP_HASH160 <hash> OP_EQUALVERIFY OP_CHECKSIG`. For a P2WSH
+    /// output the scriptCode is the witness script itself, so call this on
+    /// the witness script rather than the scriptPubKey. Any script other than
+    /// a P2WPKH program is returned unchanged; `OP_CODESEPARATOR` is not
+    /// supported.
+    pub fn script_code(&self) -> Script {
+        if self.is_v0_p2wpkh() {
+            Builder::new().push_opcode(opcodes::All::OP_DUP)
+                          .push_opcode(opcodes::All::OP_HASH160)
+                          .push_slice(&self.0[2..])
+                          .push_opcode(opcodes::All::OP_EQUALVERIFY)
+                          .push_opcode(opcodes::All::OP_CHECKSIG)
+                          .into_script()
+        } else {
+            self.clone()
+        }
+    }
+
+    /// Checks whether a script pubkey is a pay-to-anchor output, the keyless
+    /// `OP_1 <0x4e73>` witness program used for fee-bumping anchors
+    #[inline]
+    pub fn is_p2a(&self) -> bool {
+        self.0.len() == 4 &&
+        self.0[0] == opcodes::All::OP_PUSHNUM_1 as u8 &&
+        self.0[1] == opcodes::All::OP_PUSHBYTES_2 as u8 &&
+        self.0[2] == 0x4e &&
+        self.0[3] == 0x73
+    }
+
+    /// Checks whether a script pubkey is a BIP141 witness program: a version
+    /// opcode followed by a single direct push of 2 to 40 bytes
+    #[inline]
+    pub fn is_witness_program(&self) -> bool {
+        self.witness_version().is_some()
+    }
+
+    /// The witness version of a witness program script pubkey, or `None` if the
+    /// script is not a witness program
+    pub fn witness_version(&self) -> Option<u8> {
+        if self.0.len() < 4 || self.0.len() > 42 || self.0[1] as usize != self.0.len() - 2 {
+            return None;
+        }
+        match opcodes::All::from(self.0[0]).classify() {
+            opcodes::Class::PushBytes(0) => Some(0),
+            opcodes::Class::PushNum(n) if n >= 1 => Some(n as u8),
+            _ => None
+        }
+    }
+
+    /// The program of a witness program script pubkey, or `None` if the script
+    /// is not a witness program
+    pub fn witness_program(&self) -> Option<&[u8]> {
+        if self.is_witness_program() {
+            Some(&self.0[2..])
+        } else {
+            None
+        }
+    }
+
+    /// Whether a script begins with `OP_RETURN`, i.e. is a data carrier output
+    pub fn is_op_return(&self) -> bool {
+        !self.0.is_empty() && self.0[0] == opcodes::All::OP_RETURN as u8
+    }
+
     /// Whether a script can be proven to have no satisfying input
     pub fn is_provably_unspendable(&self) -> bool {
         !self.0.is_empty() && (opcodes::All::from(self.0[0]).classify() == opcodes::Class::ReturnOp ||
                                opcodes::All::from(self.0[0]).classify() == opcodes::Class::IllegalOp)
     }
 
+    /// Matches the script instruction by instruction against `template`, returning
+    /// the data of every push on success. Returns `None` if the script does not
+    /// have exactly the shape of the template or fails to parse.
+    pub fn matches_template(&self, template: &[TemplateItem]) -> Option<Vec<&[u8]>> {
+        let mut pushes = vec![];
+        let mut items = template.iter();
+        for instruction in self {
+            match (instruction, items.next()) {
+                (Instruction::Op(op), Some(&TemplateItem::Op(expected))) if op == expected => {}
+                (Instruction::PushBytes(data), Some(&TemplateItem::PushBytes(n))) if data.len() == n => {
+                    pushes.push(data);
+                }
+                (Instruction::PushBytes(data), Some(&TemplateItem::AnyPush)) => pushes.push(data),
+                _ => return None,
+            }
+        }
+        if items.next().is_some() {
+            return None;
+        }
+        Some(pushes)
+    }
+
+    /// The data of the first non-empty push in the script, skipping any
+    /// opcodes (including `OP_0`) before it. Returns `None` if there is no
+    /// such push or the script fails to parse before reaching one.
+    pub fn first_pushdata(&self) -> Option<&[u8]> {
+        for instruction in self {
+            match instruction {
+                Instruction::PushBytes(data) if !data.is_empty() => return Some(data),
+                Instruction::Error(_) => return None,
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Parses a bare `OP_m <pubkey>... OP_n OP_CHECKMULTISIG` script, returning
+    /// `m`, `n` and the keys. Returns `None` if the script has any other form,
+    /// if `m > n` or if `n` does not match the number of keys.
+    pub fn multisig_params(&self) -> Option<(usize, usize, Vec<key::PublicKey>)> {
+        fn small_int(instruction: Option<Instruction>) -> Option<usize> {
+            match instruction {
+                Some(Instruction::Op(op)) => match op.classify() {
+                    opcodes::Class::PushNum(n) if n >= 1 => Some(n as usize),
+                    _ => None
+                },
+                _ => None
+            }
+        }
+
+        let secp = Secp256k1::without_caps();
+        let mut instructions = self.into_iter().peekable();
+        let m = match small_int(instructions.next()) {
+            Some(m) => m,
+            None => return None
+        };
+        let mut keys = vec![];
+        while let Some(&Instruction::PushBytes(data)) = instructions.peek() {
+            match key::PublicKey::from_slice(&secp, data) {
+                Ok(pk) => keys.push(pk),
+                Err(_) => return None
+            }
+            instructions.next();
+        }
+        let n = match small_int(instructions.next()) {
+            Some(n) => n,
+            None => return None
+        };
+        if instructions.next() != Some(Instruction::Op(opcodes::All::OP_CHECKMULTISIG)) ||
+           instructions.next().is_some() ||
+           m > n || n != keys.len() {
+            return None;
+        }
+        Some((m, n, keys))
+    }
+
+    /// Whether the script is a standard output script under Bitcoin Core's relay
+    /// policy: p2pk, p2pkh, p2sh, a witness program (a v0 program must be 20 or
+    /// 32 bytes, programs of any later version are accepted), bare multisig with
+    /// at most 3 keys, or an `OP_RETURN` followed by pushes only, at most 83 bytes
+    /// in total
+    pub fn is_standard_output(&self) -> bool {
+        const MAX_OP_RETURN_RELAY: usize = 83;
+
+        if self.is_p2pkh() || self.is_p2sh() {
+            return true;
+        }
+        match self.witness_version() {
+            Some(0) => return self.is_v0_p2wpkh() || self.is_v0_p2wsh(),
+            Some(_) => return true,
+            None => {}
+        }
+        if self.is_op_return() {
+            return self.0.len() <= MAX_OP_RETURN_RELAY &&
+                   Instructions { data: &self.0[1..] }.all(|ins| match ins {
+                       Instruction::PushBytes(_) => true,
+                       Instruction::Op(op) => op as u8 <= opcodes::All::OP_PUSHNUM_16 as u8,
+                       Instruction::Error(_) => false,
+                   });
+        }
+        // p2pk with a compressed or uncompressed key
+        let n = self.0.len();
+        if (n == 35 || n == 67) && self.0[0] as usize == n - 2 && self.0[n - 1] == opcodes::All::OP_CHECKSIG as u8 {
+            return match self.0[1] {
+                0x02 | 0x03 => n == 35,
+                0x04 | 0x06 | 0x07 => n == 67,
+                _ => false,
+            };
+        }
+        match self.multisig_params() {
+            Some((_, n, _)) => n <= 3,
+            None => false,
+        }
+    }
+
+    /// Checks every `OP_CHECKLOCKTIMEVERIFY` and `OP_CHECKSEQUENCEVERIFY` in the
+    /// script against input `input_index` of `tx`, following BIP65 and BIP112.
+    /// Each operand is the push immediately preceding the opcode. Nothing else
+    /// is evaluated and branches are not followed, so for scripts with several
+    /// spending paths pass only the part of the script that will be executed.
+    pub fn verify_locktime(&self, tx: &Transaction, input_index: usize) -> Result<(), Error> {
+        const LOCKTIME_THRESHOLD: i64 = 500_000_000;
+        const SEQUENCE_DISABLE_FLAG: i64 = 1 << 31;
+        const SEQUENCE_TYPE_FLAG: i64 = 1 << 22;
+        const SEQUENCE_MASK: i64 = 0x0000ffff | SEQUENCE_TYPE_FLAG;
+
+        let sequence = match tx.input.get(input_index) {
+            Some(input) => input.sequence as i64,
+            None => return Err(Error::InputIndexOutOfRange(input_index)),
+        };
+        let mut last_push = None;
+        for instruction in self {
+            match instruction {
+                Instruction::PushBytes(data) => last_push = Some(read_scriptint_max(data, 5)),
+                Instruction::Op(op) => {
+                    if op == opcodes::OP_CLTV {
+                        let n = try!(last_push.clone().unwrap_or(Err(Error::EarlyEndOfScript)));
+                        let lock_time = tx.lock_time as i64;
+                        if n < 0 ||
+                           (n < LOCKTIME_THRESHOLD) != (lock_time < LOCKTIME_THRESHOLD) ||
+                           n > lock_time ||
+                           sequence == 0xffffffff {
+                            return Err(Error::UnsatisfiedLocktime);
+                        }
+                    } else if op == opcodes::OP_CSV {
+                        let n = try!(last_push.clone().unwrap_or(Err(Error::EarlyEndOfScript)));
+                        if n < 0 {
+                            return Err(Error::UnsatisfiedSequence);
+                        }
+                        if n & SEQUENCE_DISABLE_FLAG == 0 &&
+                           (tx.version < 2 ||
+                            sequence & SEQUENCE_DISABLE_FLAG != 0 ||
+                            (n & SEQUENCE_TYPE_FLAG) != (sequence & SEQUENCE_TYPE_FLAG) ||
+                            n & SEQUENCE_MASK > sequence & SEQUENCE_MASK) {
+                            return Err(Error::UnsatisfiedSequence);
+                        }
+                    }
+                    last_push = match op.classify() {
+                        opcodes::Class::PushNum(n) => Some(Ok(n as i64)),
+                        _ => None
+                    };
+                }
+                Instruction::Error(e) => return Err(e)
+            }
+        }
+        Ok(())
+    }
+
     #[cfg(feature="bitcoinconsensus")]
     /// verify spend of an input script
     /// # Parameters
@@ -367,7 +876,25 @@ impl Script {
     ///  * amount - the amount this script guards
     ///  * spending - the transaction that attempts to spend the output holding this script
     pub fn verify (&self, index: usize, amount: u64, spending: &[u8]) -> Result<(), Error> {
-        Ok(bitcoinconsensus::verify (&self.0[..], amount, spending, index)?)
+        self.verify_with_flags(index, amount, spending, VERIFY_CONSENSUS)
+    }
+
+    #[cfg(feature="bitcoinconsensus")]
+    /// Like `verify`, but enforcing `flags` rather than the consensus rules.
+    /// libbitcoinconsensus only supports the flags in `VERIFY_CONSENSUS`, so
+    /// `VERIFY_LOW_S` is checked here against the signatures pushed by the
+    /// input's scriptSig and witness, and any other flag is an error.
+    pub fn verify_with_flags(&self, index: usize, amount: u64, spending: &[u8], flags: VerifyFlags) -> Result<(), Error> {
+        let unsupported = flags & !(VERIFY_CONSENSUS | VERIFY_LOW_S);
+        if unsupported != VERIFY_NONE {
+            return Err(Error::UnsupportedVerifyFlags(unsupported));
+        }
+        let consensus_flags = (flags & VERIFY_CONSENSUS).bits();
+        try!(bitcoinconsensus::verify_with_flags(&self.0[..], amount, spending, index, consensus_flags));
+        if flags.contains(VERIFY_LOW_S) {
+            try!(check_low_s(index, spending));
+        }
+        Ok(())
     }
 }
 
@@ -380,8 +907,25 @@ impl From<Vec<u8>> for Script {
     fn from(v: Vec<u8>) -> Script { Script(v.into_boxed_slice()) }
 }
 
+/// Byte access to a script. `Script` does not `Deref` to `[u8]`; slicing goes
+/// through the range `Index` impls and this `AsRef`.
+impl AsRef<[u8]> for Script {
+    fn as_ref(&self) -> &[u8] { &self.0 }
+}
+
 impl_index_newtype!(Script, u8);
 
+/// One element of a pattern for `Script::matches_template`
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TemplateItem {
+    /// Exactly this non-push opcode
+    Op(opcodes::All),
+    /// A push of exactly this many bytes
+    PushBytes(usize),
+    /// A push of any length
+    AnyPush
+}
+
 /// A "parsed opcode" which allows iterating over a Script in a more sensible way
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Instruction<'a> {
@@ -493,6 +1037,17 @@ impl Builder {
         else { self.push_scriptint(data) }
     }
 
+    /// Adds the opcode pushing a segwit version, `OP_0` or `OP_1` to `OP_16`,
+    /// as required at the start of a witness program. Unlike `push_int` this
+    /// never falls back to a data push.
+    pub fn push_witness_version(self, version: WitnessVersion) -> Builder {
+        if version.0 == 0 {
+            self.push_opcode(opcodes::OP_FALSE)
+        } else {
+            self.push_opcode(opcodes::All::from(opcodes::OP_TRUE as u8 + version.0 - 1))
+        }
+    }
+
     /// Adds instructions to push an integer onto the stack, using the explicit
     /// encoding regardless of the availability of dedicated opcodes.
     pub fn push_scriptint(self, data: i64) -> Builder {
@@ -527,6 +1082,17 @@ impl Builder {
         self
     }
 
+    /// Adds instructions to push some arbitrary data onto the stack, failing
+    /// if the data is larger than `MAX_SCRIPT_ELEMENT_SIZE` and therefore could
+    /// never be pushed by a valid script
+    pub fn push_slice_checked(self, data: &[u8]) -> Result<Builder, Error> {
+        if data.len() > MAX_SCRIPT_ELEMENT_SIZE {
+            Err(Error::ElementTooLarge(data.len()))
+        } else {
+            Ok(self.push_slice(data))
+        }
+    }
+
     /// Adds a single opcode to the script
     pub fn push_opcode(mut self, data: opcodes::All) -> Builder {
         self.0.push(data as u8);
@@ -600,7 +1166,16 @@ impl<S: SimpleEncoder> ConsensusEncodable<S> for Script {
 impl<D: SimpleDecoder> ConsensusDecodable<D> for Script {
     #[inline]
     fn consensus_decode(d: &mut D) -> Result<Script, D::Error> {
-        Ok(Script(try!(ConsensusDecodable::consensus_decode(d))))
+        let VarInt(len): VarInt = try!(ConsensusDecodable::consensus_decode(d));
+        let max_size = d.limits().max_script_size;
+        if len > max_size as u64 {
+            return Err(d.error(format!("tried to decode script of size {} (max {})", len, max_size)));
+        }
+        let mut ret = Vec::with_capacity(len as usize);
+        for _ in 0..len {
+            ret.push(try!(d.read_u8()));
+        }
+        Ok(Script(ret.into_boxed_slice()))
     }
 }
 
@@ -642,6 +1217,94 @@ mod test {
         script = script.push_opcode(opcodes::All::OP_CHECKSIG); comp.push(0xACu8); assert_eq!(&script[..], &comp[..]);
     }
 
+    #[test]
+    fn script_push_slice_checked() {
+        let script = Builder::new().push_slice_checked(&[0; 520]).unwrap();
+        assert_eq!(script, Builder::new().push_slice(&[0; 520]));
+        assert_eq!(Builder::new().push_slice_checked(&[0; 521]), Err(Error::ElementTooLarge(521)));
+    }
+
+    #[test]
+    fn script_num() {
+        let max = SCRIPTNUM_DEFAULT_MAX_SIZE;
+        for &(n, enc) in &[(0, &[][..]), (1, &[0x01][..]), (-1, &[0x81][..]),
+                           (127, &[0x7f][..]), (-127, &[0xff][..]),
+                           (128, &[0x80, 0x00][..]), (-128, &[0x80, 0x80][..]),
+                           (255, &[0xff, 0x00][..]), (256, &[0x00, 0x01][..]),
+                           (0x7fffffff, &[0xff, 0xff, 0xff, 0x7f][..]),
+                           (-0x7fffffff, &[0xff, 0xff, 0xff, 0xff][..])] {
+            assert_eq!(ScriptNum::new(n).to_vec(), enc.to_vec());
+            assert_eq!(ScriptNum::from_slice(enc, true, max), Ok(ScriptNum::new(n)));
+        }
+
+        // zero and negative zero padded with extra bytes
+        for enc in &[&[0x00][..], &[0x80][..], &[0x00, 0x00][..], &[0x00, 0x80][..]] {
+            assert_eq!(ScriptNum::from_slice(enc, true, max), Err(Error::NonMinimalNumber));
+            assert_eq!(ScriptNum::from_slice(enc, false, max), Ok(ScriptNum::new(0)));
+        }
+        // leading (high-order) zero bytes not needed for the sign bit
+        assert_eq!(ScriptNum::from_slice(&[0x01, 0x00], true, max), Err(Error::NonMinimalNumber));
+        assert_eq!(ScriptNum::from_slice(&[0x01, 0x80], true, max), Err(Error::NonMinimalNumber));
+        assert_eq!(ScriptNum::from_slice(&[0x01, 0x80], false, max), Ok(ScriptNum::new(-1)));
+        assert_eq!(ScriptNum::from_slice(&[0xff, 0x00, 0x00], true, max), Err(Error::NonMinimalNumber));
+
+        // size limits
+        let five = [0x00, 0x00, 0x00, 0x00, 0x01];
+        assert_eq!(ScriptNum::from_slice(&five, true, max), Err(Error::NumericOverflow));
+        assert_eq!(ScriptNum::from_slice(&five, true, 5), Ok(ScriptNum::new(1 << 32)));
+        let eight = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+        assert_eq!(ScriptNum::from_slice(&eight, true, 8), Ok(ScriptNum::new(-0x7fffffffffffffff)));
+        assert_eq!(ScriptNum::new(-0x7fffffffffffffff).to_vec(), eight.to_vec());
+    }
+
+    #[test]
+    fn script_code() {
+        // BIP143 native P2WPKH example
+        let p2wpkh = hex_script!("00141d0f172a0ecb48aee1be1f2687d2963ae33f71a1");
+        assert_eq!(p2wpkh.script_code(), hex_script!("76a9141d0f172a0ecb48aee1be1f2687d2963ae33f71a188ac"));
+
+        let witness_script = hex_script!("21026dccc749adc2a9d0d89497ac511f760f45c47dc5ed9cf352a58ac706453880aeadab210255a9626aebf5e29c0e6538428ba0d1dcf6ca98ffdf086aa8ced5e0d0215ea465ac");
+        assert_eq!(witness_script.script_code(), witness_script);
+        let p2pkh = hex_script!("76a914162c5ea71c0b23f5b9022ef047c4a86470a5b07088ac");
+        assert_eq!(p2pkh.script_code(), p2pkh);
+    }
+
+    #[test]
+    fn script_semantic_eq() {
+        let p2pkh = hex_script!("76a914162c5ea71c0b23f5b9022ef047c4a86470a5b07088ac");
+        let padded = hex_script!("76a914162c5ea71c0b23f5b9022ef047c4a86470a5b07088ac6161");
+        assert!(p2pkh != padded);
+        assert!(p2pkh.semantic_eq(&padded));
+        assert_eq!(padded.normalized(), p2pkh);
+        assert_eq!(p2pkh.normalized(), p2pkh);
+
+        // only trailing no-ops are dropped
+        assert!(!hex_script!("7661a9").semantic_eq(&hex_script!("76a9")));
+        assert_eq!(hex_script!("7661a9").normalized(), hex_script!("7661a9"));
+
+        // a PUSHDATA1 of three bytes is the same push as a direct one
+        assert!(hex_script!("4c03010203").semantic_eq(&hex_script!("03010203")));
+        assert!(!hex_script!("03010203").semantic_eq(&hex_script!("03010204")));
+
+        // unparseable scripts are left alone
+        assert_eq!(hex_script!("4c0501").normalized(), hex_script!("4c0501"));
+        assert!(Script::new().semantic_eq(&hex_script!("61")));
+    }
+
+    #[test]
+    fn script_push_witness_version() {
+        assert_eq!(Builder::new().push_witness_version(WitnessVersion::v0()).into_script().into_vec(), vec![0x00]);
+        for version in 1..17 {
+            let script = Builder::new().push_witness_version(WitnessVersion::new(version).unwrap()).into_script();
+            assert_eq!(script.clone().into_vec(), vec![0x50 + version]);
+            assert_eq!(script, Builder::new().push_int(version as i64).into_script());
+        }
+        let v16 = WitnessVersion::new(16).unwrap();
+        assert_eq!(v16.to_u8(), 16);
+        assert_eq!(Builder::new().push_witness_version(v16).into_script().asm(), "OP_PUSHNUM_16");
+        assert_eq!(WitnessVersion::new(17), None);
+    }
+
     #[test]
     fn script_builder() {
         // from txid 3bb5e6434c11fb93f64574af5d116736510717f2c595eb45b52c28e31622dfff which was in my mempool when I wrote the test
@@ -654,6 +1317,17 @@ mod test {
         assert_eq!(&format!("{:x}", script), "76a91416e1ae70ff0fa102905d4af297f6912bda6cce1988ac");
     }
 
+    #[test]
+    fn script_slicing() {
+        let script = hex_script!("76a91416e1ae70ff0fa102905d4af297f6912bda6cce1988ac");
+        let bytes = "76a91416e1ae70ff0fa102905d4af297f6912bda6cce1988ac".from_hex().unwrap();
+        assert_eq!(&script[..], &bytes[..]);
+        assert_eq!(&script[3..23], &bytes[3..23]);
+        assert_eq!(&script[..2], &[0x76, 0xa9]);
+        assert_eq!(&script[23..], &[0x88, 0xac]);
+        assert_eq!(script.as_ref(), &bytes[..]);
+    }
+
     #[test]
     fn script_serialize() {
         let hex_script = "6c493046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c0121033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52".from_hex().unwrap();
@@ -704,15 +1378,245 @@ mod test {
     fn script_debug_display() {
         assert_eq!(format!("{:?}", hex_script!("6363636363686868686800")),
                    "Script(OP_IF OP_IF OP_IF OP_IF OP_IF OP_ENDIF OP_ENDIF OP_ENDIF OP_ENDIF OP_ENDIF OP_0)");
-        assert_eq!(format!("{}", hex_script!("6363636363686868686800")),
-                   "Script(OP_IF OP_IF OP_IF OP_IF OP_IF OP_ENDIF OP_ENDIF OP_ENDIF OP_ENDIF OP_ENDIF OP_0)");
-        assert_eq!(format!("{}", hex_script!("2102715e91d37d239dea832f1460e91e368115d8ca6cc23a7da966795abad9e3b699ac")),
+        assert_eq!(format!("{}", hex_script!("6363636363686868686800")), "6363636363686868686800");
+        assert_eq!(format!("{:?}", hex_script!("2102715e91d37d239dea832f1460e91e368115d8ca6cc23a7da966795abad9e3b699ac")),
                    "Script(OP_PUSHBYTES_33 02715e91d37d239dea832f1460e91e368115d8ca6cc23a7da966795abad9e3b699 OP_CHECKSIG)");
         // Elements Alpha peg-out transaction with some signatures removed for brevity. Mainly to test PUSHDATA1
-        assert_eq!(format!("{}", hex_script!("0047304402202457e78cc1b7f50d0543863c27de75d07982bde8359b9e3316adec0aec165f2f02200203fd331c4e4a4a02f48cf1c291e2c0d6b2f7078a784b5b3649fca41f8794d401004cf1552103244e602b46755f24327142a0517288cebd159eccb6ccf41ea6edf1f601e9af952103bbbacc302d19d29dbfa62d23f37944ae19853cf260c745c2bea739c95328fcb721039227e83246bd51140fe93538b2301c9048be82ef2fb3c7fc5d78426ed6f609ad210229bf310c379b90033e2ecb07f77ecf9b8d59acb623ab7be25a0caed539e2e6472103703e2ed676936f10b3ce9149fa2d4a32060fb86fa9a70a4efe3f21d7ab90611921031e9b7c6022400a6bb0424bbcde14cff6c016b91ee3803926f3440abf5c146d05210334667f975f55a8455d515a2ef1c94fdfa3315f12319a14515d2a13d82831f62f57ae")),
+        assert_eq!(format!("{:?}", hex_script!("0047304402202457e78cc1b7f50d0543863c27de75d07982bde8359b9e3316adec0aec165f2f02200203fd331c4e4a4a02f48cf1c291e2c0d6b2f7078a784b5b3649fca41f8794d401004cf1552103244e602b46755f24327142a0517288cebd159eccb6ccf41ea6edf1f601e9af952103bbbacc302d19d29dbfa62d23f37944ae19853cf260c745c2bea739c95328fcb721039227e83246bd51140fe93538b2301c9048be82ef2fb3c7fc5d78426ed6f609ad210229bf310c379b90033e2ecb07f77ecf9b8d59acb623ab7be25a0caed539e2e6472103703e2ed676936f10b3ce9149fa2d4a32060fb86fa9a70a4efe3f21d7ab90611921031e9b7c6022400a6bb0424bbcde14cff6c016b91ee3803926f3440abf5c146d05210334667f975f55a8455d515a2ef1c94fdfa3315f12319a14515d2a13d82831f62f57ae")),
                    "Script(OP_0 OP_PUSHBYTES_71 304402202457e78cc1b7f50d0543863c27de75d07982bde8359b9e3316adec0aec165f2f02200203fd331c4e4a4a02f48cf1c291e2c0d6b2f7078a784b5b3649fca41f8794d401 OP_0 OP_PUSHDATA1 552103244e602b46755f24327142a0517288cebd159eccb6ccf41ea6edf1f601e9af952103bbbacc302d19d29dbfa62d23f37944ae19853cf260c745c2bea739c95328fcb721039227e83246bd51140fe93538b2301c9048be82ef2fb3c7fc5d78426ed6f609ad210229bf310c379b90033e2ecb07f77ecf9b8d59acb623ab7be25a0caed539e2e6472103703e2ed676936f10b3ce9149fa2d4a32060fb86fa9a70a4efe3f21d7ab90611921031e9b7c6022400a6bb0424bbcde14cff6c016b91ee3803926f3440abf5c146d05210334667f975f55a8455d515a2ef1c94fdfa3315f12319a14515d2a13d82831f62f57ae)");
     }
 
+    #[test]
+    fn script_asm() {
+        // p2tr scriptPubKey from BIP341
+        let p2tr = hex_script!("512053a1f6e454df1aa2776a2814a721372d6258050de330b3c6d10ee8f4e0dda343");
+        assert_eq!(p2tr.asm(),
+                   "OP_PUSHNUM_1 OP_PUSHBYTES_32 53a1f6e454df1aa2776a2814a721372d6258050de330b3c6d10ee8f4e0dda343");
+        assert_eq!(format!("{:?}", p2tr), format!("Script({})", p2tr.asm()));
+        assert_eq!(hex_script!("0014ee61d57ab51b9d212335b1dba62794ac20d2bcf9").asm(),
+                   "OP_0 OP_PUSHBYTES_20 ee61d57ab51b9d212335b1dba62794ac20d2bcf9");
+        assert_eq!(hex_script!("60").asm(), "OP_PUSHNUM_16");
+        assert_eq!(Script::new().asm(), "");
+    }
+
+    #[test]
+    fn script_hex_round_trip() {
+        use std::str::FromStr;
+
+        let hex = "76a91416e1ae70ff0fa102905d4af297f6912bda6cce1988ac";
+        let script = Script::from_str(hex).unwrap();
+        assert_eq!(script, hex_script!(hex));
+        assert!(script.is_p2pkh());
+        assert_eq!(script.to_string(), hex);
+        assert_eq!(Script::from_str(&script.to_string()), Ok(script));
+
+        assert_eq!(Script::from_str(""), Ok(Script::new()));
+        assert_eq!(Script::from_str("76a"), Err(Error::InvalidHex));
+        assert_eq!(Script::from_str("76ag"), Err(Error::InvalidHex));
+    }
+
+    #[test]
+    fn script_matches_template() {
+        use blockdata::opcodes::All::*;
+
+        let p2pkh = [TemplateItem::Op(OP_DUP), TemplateItem::Op(OP_HASH160), TemplateItem::PushBytes(20),
+                     TemplateItem::Op(OP_EQUALVERIFY), TemplateItem::Op(OP_CHECKSIG)];
+        let script = hex_script!("76a91416e1ae70ff0fa102905d4af297f6912bda6cce1988ac");
+        assert_eq!(script.matches_template(&p2pkh),
+                   Some(vec![&"16e1ae70ff0fa102905d4af297f6912bda6cce19".from_hex().unwrap()[..]]));
+
+        // near misses: wrong final opcode, short push, truncated, extra opcode
+        assert_eq!(hex_script!("76a91416e1ae70ff0fa102905d4af297f6912bda6cce1988ad").matches_template(&p2pkh), None);
+        assert_eq!(hex_script!("76a91316e1ae70ff0fa102905d4af297f6912bda6cce88ac").matches_template(&p2pkh), None);
+        assert_eq!(hex_script!("76a91416e1ae70ff0fa102905d4af297f6912bda6cce1988").matches_template(&p2pkh), None);
+        assert_eq!(hex_script!("76a91416e1ae70ff0fa102905d4af297f6912bda6cce1988ac75").matches_template(&p2pkh), None);
+        assert_eq!(hex_script!("76a9").matches_template(&p2pkh), None);
+        assert_eq!(hex_script!("76a94c").matches_template(&p2pkh), None);
+
+        let push_drop = [TemplateItem::AnyPush, TemplateItem::Op(OP_DROP)];
+        assert_eq!(hex_script!("0075").matches_template(&push_drop), Some(vec![&[][..]]));
+        assert_eq!(hex_script!("0301020375").matches_template(&push_drop), Some(vec![&[1, 2, 3][..]]));
+        assert_eq!(Script::new().matches_template(&[]), Some(vec![]));
+    }
+
+    #[test]
+    fn script_witness_program() {
+        // v0 p2wpkh
+        let script = hex_script!("0014751e76e8199196d454941c45d1b3a323f1433bd6");
+        assert!(script.is_witness_program());
+        assert_eq!(script.witness_version(), Some(0));
+        assert_eq!(script.witness_program(), Some(&"751e76e8199196d454941c45d1b3a323f1433bd6".from_hex().unwrap()[..]));
+        // v1 p2tr
+        let script = hex_script!("5120a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c");
+        assert_eq!(script.witness_version(), Some(1));
+        assert_eq!(script.witness_program().map(|p| p.len()), Some(32));
+        // v16, minimal and maximal program sizes
+        assert_eq!(hex_script!("60020001").witness_version(), Some(16));
+        assert_eq!(hex_script!("002800000000000000000000000000000000000000000000000000000000000000000000000000000000").witness_version(), Some(0));
+
+        // trailing opcode
+        assert!(!hex_script!("0014751e76e8199196d454941c45d1b3a323f1433bd675").is_witness_program());
+        assert_eq!(hex_script!("0014751e76e8199196d454941c45d1b3a323f1433bd675").witness_program(), None);
+        // program too short or too long
+        assert!(!hex_script!("000100").is_witness_program());
+        assert!(!hex_script!("00290000000000000000000000000000000000000000000000000000000000000000000000000000000000").is_witness_program());
+        // not a version opcode, or a non-direct push
+        assert!(!hex_script!("4f020001").is_witness_program());
+        assert!(!hex_script!("76020001").is_witness_program());
+        assert!(!hex_script!("004c020001").is_witness_program());
+        assert!(!hex_script!("76a91416e1ae70ff0fa102905d4af297f6912bda6cce1988ac").is_witness_program());
+    }
+
+    #[test]
+    fn script_first_pushdata() {
+        let hash = "751e76e8199196d454941c45d1b3a323f1433bd6".from_hex().unwrap();
+        // p2wpkh, p2pkh
+        assert_eq!(hex_script!("0014751e76e8199196d454941c45d1b3a323f1433bd6").first_pushdata(), Some(&hash[..]));
+        assert_eq!(hex_script!("76a914751e76e8199196d454941c45d1b3a323f1433bd688ac").first_pushdata(), Some(&hash[..]));
+        // PUSHDATA1 and PUSHDATA2 prefixes
+        assert_eq!(hex_script!("6a4c03010203").first_pushdata(), Some(&[1, 2, 3][..]));
+        assert_eq!(hex_script!("4d0300010203").first_pushdata(), Some(&[1, 2, 3][..]));
+
+        assert_eq!(hex_script!("6a").first_pushdata(), None);
+        assert_eq!(hex_script!("").first_pushdata(), None);
+        assert_eq!(hex_script!("6a4c05010203").first_pushdata(), None);
+    }
+
+    #[test]
+    fn script_multisig_params() {
+        // 5-of-7 redeem script from the address tests
+        let script = hex_script!("552103a765fc35b3f210b95223846b36ef62a4e53e34e2925270c2c7906b92c9f718eb2103c327511374246759ec8d0b89fa6c6b23b33e11f92c5bc155409d86de0c79180121038cae7406af1f12f4786d820a1466eec7bc5785a1b5e4a387eca6d797753ef6db2103252bfb9dcaab0cd00353f2ac328954d791270203d66c2be8b430f115f451b8a12103e79412d42372c55dd336f2eb6eb639ef9d74a22041ba79382c74da2338fe58ad21035049459a4ebc00e876a9eef02e72a3e70202d3d1f591fc0dd542f93f642021f82102016f682920d9723c61b27f562eb530c926c00106004798b6471e8c52c60ee02057ae");
+        let (m, n, keys) = script.multisig_params().unwrap();
+        assert_eq!((m, n), (5, 7));
+        assert_eq!(keys.len(), 7);
+        assert!(keys.iter().all(|pk| pk.compressed));
+        assert_eq!(keys[0].to_string(), "03a765fc35b3f210b95223846b36ef62a4e53e34e2925270c2c7906b92c9f718eb");
+
+        // 1-of-1 with an uncompressed key
+        let (m, n, keys) = hex_script!("514104ea1feff861b51fe3f5f8a3b12d0f4712db80e919548a80839fc47c6a21e66d957e9c5d8cd108c7a2d2324bad71f9904ac0ae7336507d785b17a2c115e427a32f51ae").multisig_params().unwrap();
+        assert_eq!((m, n), (1, 1));
+        assert!(!keys[0].compressed);
+
+        // m > n, n not matching the key count, wrong final opcode, trailing data, not a key
+        assert!(hex_script!("522103a765fc35b3f210b95223846b36ef62a4e53e34e2925270c2c7906b92c9f718eb51ae").multisig_params().is_none());
+        assert!(hex_script!("512103a765fc35b3f210b95223846b36ef62a4e53e34e2925270c2c7906b92c9f718eb52ae").multisig_params().is_none());
+        assert!(hex_script!("512103a765fc35b3f210b95223846b36ef62a4e53e34e2925270c2c7906b92c9f718eb51af").multisig_params().is_none());
+        assert!(hex_script!("512103a765fc35b3f210b95223846b36ef62a4e53e34e2925270c2c7906b92c9f718eb51ae75").multisig_params().is_none());
+        assert!(hex_script!("5102010251ae").multisig_params().is_none());
+        assert!(hex_script!("76a914751e76e8199196d454941c45d1b3a323f1433bd688ac").multisig_params().is_none());
+        assert!(Script::new().multisig_params().is_none());
+    }
+
+    #[test]
+    fn script_p2a() {
+        let script = hex_script!("51024e73");
+        assert!(script.is_p2a());
+        assert!(script.is_witness_program());
+        assert!(!hex_script!("51024e74").is_p2a());
+        assert!(!hex_script!("00024e73").is_p2a());
+        assert!(!hex_script!("51034e7300").is_p2a());
+    }
+
+    #[test]
+    fn script_empty_classification() {
+        use network::constants::Network;
+        use util::address::Address;
+
+        let script = Script::new();
+        assert!(script.is_empty());
+        assert!(!script.is_p2sh());
+        assert!(!script.is_p2pkh());
+        assert!(!script.is_p2pk());
+        assert!(!script.is_v0_p2wsh());
+        assert!(!script.is_v0_p2wpkh());
+        assert!(!script.is_witness_program());
+        assert!(!script.is_p2a());
+        assert_eq!(script.witness_version(), None);
+        assert_eq!(script.witness_program(), None);
+        assert!(!script.is_op_return());
+        assert!(!script.is_provably_unspendable());
+        assert!(!script.is_standard_output());
+        assert_eq!(script.first_pushdata(), None);
+        assert!(script.multisig_params().is_none());
+        assert_eq!(script.matches_template(&[TemplateItem::AnyPush]), None);
+        assert_eq!(script.matches_template(&[]), Some(vec![]));
+        assert_eq!(Address::from_script(&script, Network::Bitcoin), None);
+        assert_eq!(script.to_string(), "");
+    }
+
+    #[test]
+    fn script_is_standard_output() {
+        // bare 1-of-2 multisig
+        assert!(hex_script!("512103a765fc35b3f210b95223846b36ef62a4e53e34e2925270c2c7906b92c9f718eb2103c327511374246759ec8d0b89fa6c6b23b33e11f92c5bc155409d86de0c79180152ae").is_standard_output());
+        // the 5-of-7 is too large to be bare
+        assert!(!hex_script!("552103a765fc35b3f210b95223846b36ef62a4e53e34e2925270c2c7906b92c9f718eb2103c327511374246759ec8d0b89fa6c6b23b33e11f92c5bc155409d86de0c79180121038cae7406af1f12f4786d820a1466eec7bc5785a1b5e4a387eca6d797753ef6db2103252bfb9dcaab0cd00353f2ac328954d791270203d66c2be8b430f115f451b8a12103e79412d42372c55dd336f2eb6eb639ef9d74a22041ba79382c74da2338fe58ad21035049459a4ebc00e876a9eef02e72a3e70202d3d1f591fc0dd542f93f642021f82102016f682920d9723c61b27f562eb530c926c00106004798b6471e8c52c60ee02057ae").is_standard_output());
+
+        assert!(hex_script!("76a914751e76e8199196d454941c45d1b3a323f1433bd688ac").is_standard_output());
+        assert!(hex_script!("a914acc91e6fef5c7f24e5c8b3f11a664aa8f1352ffd87").is_standard_output());
+        assert!(hex_script!("0014751e76e8199196d454941c45d1b3a323f1433bd6").is_standard_output());
+        assert!(hex_script!("5120d6889cb081036e0faefa3a35157ad71086b123b2b144b649798b494c300a961d").is_standard_output());
+        assert!(hex_script!("2103a765fc35b3f210b95223846b36ef62a4e53e34e2925270c2c7906b92c9f718ebac").is_standard_output());
+        assert!(!hex_script!("0015751e76e8199196d454941c45d1b3a323f1433bd600").is_standard_output());
+
+        // OP_RETURN with 80 bytes of data is the largest allowed
+        let mut data = vec![0x6a, 0x4c, 80];
+        data.extend_from_slice(&[0xab; 80]);
+        assert!(Script::from(data.clone()).is_standard_output());
+        data[2] = 81;
+        data.push(0xab);
+        assert!(!Script::from(data).is_standard_output());
+        assert!(hex_script!("6a").is_standard_output());
+        assert!(!hex_script!("6a0102ac").is_standard_output());
+
+        assert!(!hex_script!("ac").is_standard_output());
+        assert!(!Script::new().is_standard_output());
+    }
+
+    #[test]
+    fn script_verify_locktime() {
+        use blockdata::transaction::{TransactionBuilder, TxIn};
+        use util::hash::Sha256dHash;
+
+        let tx = |version, lock_time, sequence| {
+            TransactionBuilder::new()
+                .version(version)
+                .lock_time(lock_time)
+                .add_input(TxIn::new(Sha256dHash::default(), 0, Script::new(), sequence))
+                .build()
+        };
+
+        // <500000> OP_CLTV OP_DROP <pubkey> OP_CHECKSIG
+        let cltv = Builder::new().push_int(500000)
+                                 .push_opcode(opcodes::OP_CLTV)
+                                 .push_opcode(opcodes::All::OP_DROP)
+                                 .push_slice(&[2; 33])
+                                 .push_opcode(opcodes::All::OP_CHECKSIG)
+                                 .into_script();
+        assert_eq!(cltv.verify_locktime(&tx(1, 500000, 0xfffffffe), 0), Ok(()));
+        assert_eq!(cltv.verify_locktime(&tx(1, 600000, 0), 0), Ok(()));
+        assert_eq!(cltv.verify_locktime(&tx(1, 499999, 0xfffffffe), 0), Err(Error::UnsatisfiedLocktime));
+        assert_eq!(cltv.verify_locktime(&tx(1, 500000, 0xffffffff), 0), Err(Error::UnsatisfiedLocktime));
+        // timestamp locktime against a height
+        assert_eq!(cltv.verify_locktime(&tx(1, 1500000000, 0), 0), Err(Error::UnsatisfiedLocktime));
+
+        // 5-byte operand
+        let cltv = Builder::new().push_int(0xffffffff).push_opcode(opcodes::OP_CLTV).into_script();
+        assert_eq!(cltv.verify_locktime(&tx(1, 0xffffffff, 0), 0), Ok(()));
+        // missing operand
+        let cltv = Builder::new().push_opcode(opcodes::OP_CLTV).into_script();
+        assert_eq!(cltv.verify_locktime(&tx(1, 0, 0), 0), Err(Error::EarlyEndOfScript));
+
+        // <10> OP_CSV
+        let csv = Builder::new().push_int(10).push_opcode(opcodes::OP_CSV).into_script();
+        assert_eq!(csv.verify_locktime(&tx(2, 0, 10), 0), Ok(()));
+        assert_eq!(csv.verify_locktime(&tx(2, 0, 9), 0), Err(Error::UnsatisfiedSequence));
+        assert_eq!(csv.verify_locktime(&tx(1, 0, 10), 0), Err(Error::UnsatisfiedSequence));
+        assert_eq!(csv.verify_locktime(&tx(2, 0, (1 << 22) | 10), 0), Err(Error::UnsatisfiedSequence));
+
+        // nonexistent input
+        assert_eq!(csv.verify_locktime(&tx(2, 0, 10), 1), Err(Error::InputIndexOutOfRange(1)));
+    }
+
     #[test]
     fn script_p2sh_p2p2k_template() {
         // random outputs I picked out of the mempool
@@ -758,5 +1662,42 @@ mod test {
 		let spending = "010000000001011f97548fbbe7a0db7588a66e18d803d0089315aa7d4cc28360b6ec50ef36718a0100000000ffffffff02df1776000000000017a9146c002a686959067f4866b8fb493ad7970290ab728757d29f0000000000220020701a8d401c84fb13e6baf169d59684e17abd9fa216c8cc5b9fc63d622ff8c58d04004730440220565d170eed95ff95027a69b313758450ba84a01224e1f7f130dda46e94d13f8602207bdd20e307f062594022f12ed5017bbf4a055a06aea91c10110a0e3bb23117fc014730440220647d2dc5b15f60bc37dc42618a370b2a1490293f9e5c8464f53ec4fe1dfe067302203598773895b4b16d37485cbe21b337f4e4b650739880098c592553add7dd4355016952210375e00eb72e29da82b89367947f29ef34afb75e8654f6ea368e0acdfd92976b7c2103a1b26313f430c4b15bb1fdce663207659d8cac749a0e53d70eff01874496feff2103c96d495bfdd5ba4145e3e046fee45e84a8a48ad05bd8dbb395c011a32cf9f88053ae00000000".from_hex().unwrap();
 		spent.verify(0, 18393430, spending.as_slice()).unwrap();
 	}
+
+	#[test]
+	#[cfg(feature="bitcoinconsensus")]
+	fn test_verify_flags () {
+		use blockdata::witness::Witness;
+
+		// the transaction from test_bitcoinconsensus, with the S value of the first signature replaced by n - S
+		let spent = Builder::from("0020701a8d401c84fb13e6baf169d59684e17abd9fa216c8cc5b9fc63d622ff8c58d".from_hex().unwrap()).into_script();
+		let spending = "010000000001011f97548fbbe7a0db7588a66e18d803d0089315aa7d4cc28360b6ec50ef36718a0100000000ffffffff02df1776000000000017a9146c002a686959067f4866b8fb493ad7970290ab728757d29f0000000000220020701a8d401c84fb13e6baf169d59684e17abd9fa216c8cc5b9fc63d622ff8c58d04004730440220565d170eed95ff95027a69b313758450ba84a01224e1f7f130dda46e94d13f8602207bdd20e307f062594022f12ed5017bbf4a055a06aea91c10110a0e3bb23117fc014730440220647d2dc5b15f60bc37dc42618a370b2a1490293f9e5c8464f53ec4fe1dfe067302203598773895b4b16d37485cbe21b337f4e4b650739880098c592553add7dd4355016952210375e00eb72e29da82b89367947f29ef34afb75e8654f6ea368e0acdfd92976b7c2103a1b26313f430c4b15bb1fdce663207659d8cac749a0e53d70eff01874496feff2103c96d495bfdd5ba4145e3e046fee45e84a8a48ad05bd8dbb395c011a32cf9f88053ae00000000";
+		let high_s = spending.replace("4730440220565d170eed95ff95027a69b313758450ba84a01224e1f7f130dda46e94d13f8602207bdd20e307f062594022f12ed5017bbf4a055a06aea91c10110a0e3bb23117fc01",
+		                              "4830450220565d170eed95ff95027a69b313758450ba84a01224e1f7f130dda46e94d13f860221008422df1cf80f9da6bfdd0ed12afe843f70a982e0009f842baec850511e05294501");
+		assert!(high_s != spending);
+		let spending = spending.from_hex().unwrap();
+		let high_s = high_s.from_hex().unwrap();
+
+		assert_eq!(VerifyFlags::default(), VERIFY_CONSENSUS);
+		spent.verify_with_flags(0, 18393430, &spending, VERIFY_CONSENSUS | VERIFY_LOW_S).unwrap();
+		spent.verify(0, 18393430, &high_s).unwrap();
+		spent.verify_with_flags(0, 18393430, &high_s, VERIFY_CONSENSUS).unwrap();
+		assert_eq!(spent.verify_with_flags(0, 18393430, &high_s, VERIFY_CONSENSUS | VERIFY_LOW_S), Err(Error::SigHighS));
+		// the sighash byte is not checked, even when it is not a standard type
+		let low_s_sig = "30440220565d170eed95ff95027a69b313758450ba84a01224e1f7f130dda46e94d13f8602207bdd20e307f062594022f12ed5017bbf4a055a06aea91c10110a0e3bb23117fc";
+		let high_s_sig = "30450220565d170eed95ff95027a69b313758450ba84a01224e1f7f130dda46e94d13f860221008422df1cf80f9da6bfdd0ed12afe843f70a982e0009f842baec850511e052945";
+		for &(sig, hash_ty, ref result) in &[(high_s_sig, "01", Err(Error::SigHighS)),
+		                                  (high_s_sig, "00", Err(Error::SigHighS)),
+		                                  (high_s_sig, "04", Err(Error::SigHighS)),
+		                                  (low_s_sig, "04", Ok(()))] {
+			let mut tx: Transaction = deserialize(&spending).unwrap();
+			tx.input[0].witness = Witness::from(vec![(sig.to_owned() + hash_ty).from_hex().unwrap()]);
+			assert_eq!(&check_low_s(0, &serialize(&tx).unwrap()), result);
+		}
+		// without the witness flag the witness program is anyone-can-spend
+		spent.verify_with_flags(0, 0, &spending, VERIFY_P2SH).unwrap();
+		assert!(spent.verify_with_flags(0, 0, &spending, VERIFY_CONSENSUS).is_err());
+		assert_eq!(spent.verify_with_flags(0, 18393430, &spending, VERIFY_CONSENSUS | VERIFY_CLEANSTACK | VERIFY_TAPROOT),
+		           Err(Error::UnsupportedVerifyFlags(VERIFY_CLEANSTACK | VERIFY_TAPROOT)));
+	}
 }
 
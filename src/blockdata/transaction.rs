@@ -25,15 +25,25 @@
 
 use byteorder::{LittleEndian, WriteBytesExt};
 use std::default::Default;
-use std::fmt;
+use std::{fmt, iter, slice};
 #[cfg(feature="bitcoinconsensus")] use std::collections::HashMap;
 use serde;
 
+use secp256k1::{Message, Secp256k1};
+use secp256k1::key::PublicKey;
+
+use util::Error;
+use util::address::Address;
+use util::bip143::SighashComponents;
+use util::ecdsa::EcdsaSig;
 use util::hash::Sha256dHash;
+use blockdata::constants;
 #[cfg(feature="bitcoinconsensus")] use blockdata::script;
-use blockdata::script::Script;
-use network::serialize::{serialize, BitcoinHash, SimpleEncoder, SimpleDecoder};
-use network::encodable::{ConsensusEncodable, ConsensusDecodable, VarInt};
+use blockdata::script::{Instruction, Script, MAX_SCRIPT_SIZE};
+use blockdata::witness::Witness;
+use network::serialize::{serialize, serialized_size, BitcoinHash, SimpleEncoder, SimpleDecoder};
+use network::constants::Network;
+use network::encodable::{ConsensusEncodable, ConsensusDecodable, VarInt, MAX_VEC_SIZE};
 
 /// A reference to a transaction output
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
@@ -45,6 +55,22 @@ pub struct TxOutRef {
 }
 serde_struct_impl!(TxOutRef, txid, index);
 
+impl TxOutRef {
+    /// The null reference used as the sole input of a coinbase transaction:
+    /// an all-zero txid and index `0xFFFFFFFF`
+    pub fn null() -> TxOutRef {
+        TxOutRef {
+            txid: Default::default(),
+            index: 0xFFFFFFFF
+        }
+    }
+
+    /// Whether this is the null reference spent by coinbase transactions
+    pub fn is_null(&self) -> bool {
+        *self == TxOutRef::null()
+    }
+}
+
 impl fmt::Display for TxOutRef {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}:{}", self.txid, self.index)
@@ -66,15 +92,18 @@ pub struct TxIn {
     /// to ignore this feature. This is generally never used since
     /// the miner behaviour cannot be enforced.
     pub sequence: u32,
-    /// Witness data: an array of byte-arrays.
+    /// Witness data: a stack of byte-arrays.
     /// Note that this field is *not* (de)serialized with the rest of the TxIn in
     /// ConsensusEncodable/ConsennsusDecodable, as it is (de)serialized at the end of the full
     /// Transaction. It *is* (de)serialized with the rest of the TxIn in other (de)serializationn
     /// routines.
-    pub witness: Vec<Vec<u8>>
+    pub witness: Witness
 }
 serde_struct_impl!(TxIn, prev_hash, prev_index, script_sig, sequence, witness);
 
+/// bitcoin-core's default dust relay fee, in satoshis per 1000 virtual bytes
+pub const DUST_RELAY_TX_FEE: u64 = 3000;
+
 /// A transaction output, which defines new coins to be created from old ones.
 #[derive(Clone, PartialEq, Eq, Debug, Hash)]
 pub struct TxOut {
@@ -85,6 +114,84 @@ pub struct TxOut {
 }
 serde_struct_impl!(TxOut, value, script_pubkey);
 
+impl TxIn {
+    /// Creates an input spending output `prev_index` of transaction `prev_hash`,
+    /// with an empty witness
+    pub fn new(prev_hash: Sha256dHash, prev_index: u32, script_sig: Script, sequence: u32) -> TxIn {
+        TxIn {
+            prev_hash: prev_hash,
+            prev_index: prev_index,
+            script_sig: script_sig,
+            sequence: sequence,
+            witness: Witness::new(),
+        }
+    }
+
+    /// The consensus-serialized size of the input, excluding its witness
+    pub fn size(&self) -> usize {
+        32 + 4 + // outpoint
+        VarInt(self.script_sig.len() as u64).encoded_length() as usize +
+        self.script_sig.len() +
+        4 // nSequence
+    }
+
+    /// The serialized size of the input's witness as it appears in a segwit
+    /// transaction, including the element count. In a transaction where no
+    /// input has a witness, witnesses are not serialized at all.
+    pub fn witness_size(&self) -> usize {
+        self.witness.serialized_len() as usize
+    }
+}
+
+impl TxOut {
+    /// Creates an output paying `value` satoshis to `script_pubkey`
+    pub fn new(value: u64, script_pubkey: Script) -> TxOut {
+        TxOut {
+            value: value,
+            script_pubkey: script_pubkey,
+        }
+    }
+
+    /// The consensus-serialized size of the output
+    pub fn size(&self) -> usize {
+        8 + // value
+        VarInt(self.script_pubkey.len() as u64).encoded_length() as usize +
+        self.script_pubkey.len()
+    }
+
+    /// The smallest value this output may have without being dust at
+    /// `dust_relay_fee` satoshis per 1000 virtual bytes, i.e. the fee for
+    /// creating and spending it rounded up, as in bitcoin-core's `GetDustThreshold`.
+    /// `OP_RETURN` and oversize outputs have no threshold.
+    pub fn dust_threshold(&self, dust_relay_fee: u64) -> u64 {
+        if self.script_pubkey.is_op_return() || self.script_pubkey.len() > MAX_SCRIPT_SIZE {
+            return 0;
+        }
+        // the size of a typical input spending the output, with witness data discounted
+        let spend_size = if self.script_pubkey.is_witness_program() {
+            32 + 4 + 1 + 107 / 4 + 4
+        } else {
+            32 + 4 + 1 + 107 + 4
+        };
+        ((self.size() + spend_size) as u64 * dust_relay_fee + 999) / 1000
+    }
+
+    /// Whether the output is worth less than its `dust_threshold`, and so would
+    /// not be relayed by bitcoin-core. `DUST_RELAY_TX_FEE` is its default fee.
+    pub fn is_dust(&self, dust_relay_fee: u64) -> bool {
+        self.value < self.dust_threshold(dust_relay_fee)
+    }
+
+    /// Like `new`, but fails with `Error::ValueOverflow` if `value` exceeds
+    /// the money supply
+    pub fn new_checked(value: u64, script_pubkey: Script) -> Result<TxOut, Error> {
+        if !constants::is_valid_money_range(value) {
+            return Err(Error::ValueOverflow);
+        }
+        Ok(TxOut::new(value, script_pubkey))
+    }
+}
+
 // This is used as a "null txout" in consensus signing code
 impl Default for TxOut {
     fn default() -> TxOut {
@@ -93,7 +200,7 @@ impl Default for TxOut {
 }
 
 /// A Bitcoin transaction, which describes an authenticated movement of coins
-#[derive(Clone, PartialEq, Eq, Debug, Hash)]
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct Transaction {
     /// The protocol version, should always be 1.
     pub version: u32,
@@ -107,6 +214,25 @@ pub struct Transaction {
 }
 serde_struct_impl!(Transaction, version, lock_time, input, output);
 
+/// Shows the txid and input/output counts; the alternate form `{:#?}` lists
+/// every input and output as well
+impl fmt::Debug for Transaction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if f.alternate() {
+            f.debug_struct("Transaction")
+             .field("txid", &format_args!("{}", self.txid()))
+             .field("version", &self.version)
+             .field("lock_time", &self.lock_time)
+             .field("input", &self.input)
+             .field("output", &self.output)
+             .finish()
+        } else {
+            write!(f, "Transaction {{ txid: {}, version: {}, lock_time: {}, inputs: {}, outputs: {} }}",
+                   self.txid(), self.version, self.lock_time, self.input.len(), self.output.len())
+        }
+    }
+}
+
 impl Transaction {
     /// Computes a "normalized TXID" which does not include any signatures.
     /// This gives a way to identify a transaction that is ``the same'' as
@@ -115,7 +241,7 @@ impl Transaction {
         let cloned_tx = Transaction {
             version: self.version,
             lock_time: self.lock_time,
-            input: self.input.iter().map(|txin| TxIn { script_sig: Script::new(), witness: vec![], .. *txin }).collect(),
+            input: self.input.iter().map(|txin| TxIn { script_sig: Script::new(), witness: Witness::new(), .. *txin }).collect(),
             output: self.output.clone(),
         };
         cloned_tx.bitcoin_hash()
@@ -136,6 +262,13 @@ impl Transaction {
         enc.into_hash()
     }
 
+    /// Computes the witness txid, which commits to the witnesses as well. This
+    /// is the same as `BitcoinHash::bitcoin_hash()`, and equals the txid for
+    /// transactions without any witness.
+    pub fn wtxid(&self) -> Sha256dHash {
+        self.bitcoin_hash()
+    }
+
     /// Computes a signature hash for a given input index with a given sighash flag.
     /// To actually produce a scriptSig, this hash needs to be run through an
     /// ECDSA signer, the SigHashType appended to the resulting sig, and a
@@ -176,7 +309,7 @@ impl Transaction {
                 script_sig: script_pubkey.clone(),
                 prev_index: self.input[input_index].prev_index,
                 sequence: self.input[input_index].sequence,
-                witness: vec![],
+                witness: Witness::new(),
             }];
         } else {
             tx.input = Vec::with_capacity(self.input.len());
@@ -186,7 +319,7 @@ impl Transaction {
                     prev_index: input.prev_index,
                     script_sig: if n == input_index { script_pubkey.clone() } else { Script::new() },
                     sequence: if n != input_index && (sighash == SigHashType::Single || sighash == SigHashType::None) { 0 } else { input.sequence },
-                    witness: vec![],
+                    witness: Witness::new(),
                 });
             }
         }
@@ -209,6 +342,109 @@ impl Transaction {
         Sha256dHash::from_data(&raw_vec)
     }
 
+    /// Verifies `sig` by `pubkey` on the input at `input_index`, which spends
+    /// `prevout`. For v0 witness outputs, including ones nested in P2SH, the
+    /// sighash is computed per BIP143, which is only supported for
+    /// `SigHashType::All`. Other outputs use the legacy sighash. The redeem
+    /// script of a P2SH output is the last push of the scriptSig, and the
+    /// witness script of a P2WSH output is the last witness element.
+    ///
+    /// libsecp256k1 rejects signatures with a high S value, so these fail to
+    /// verify even though they are valid by consensus. Fails with
+    /// `Error::InputIndexOutOfRange` if there is no input at `input_index`.
+    pub fn verify_input_ecdsa(&self, secp: &Secp256k1, input_index: usize, prevout: &TxOut,
+                              pubkey: &PublicKey, sig: &EcdsaSig) -> Result<(), Error> {
+        let input = match self.input.get(input_index) {
+            Some(input) => input,
+            None => return Err(Error::InputIndexOutOfRange(input_index)),
+        };
+
+        let mut script_pubkey = prevout.script_pubkey.clone();
+        if script_pubkey.is_p2sh() {
+            if let Some(Instruction::PushBytes(redeem)) = input.script_sig.into_iter().last() {
+                script_pubkey = Script::from(redeem.to_vec());
+            }
+        }
+
+        let script_code = if script_pubkey.is_v0_p2wpkh() {
+            Some(script_pubkey.script_code())
+        } else if script_pubkey.is_v0_p2wsh() {
+            Some(Script::from(input.witness.last().unwrap_or(&[]).to_vec()))
+        } else {
+            None
+        };
+
+        let sighash = match script_code {
+            Some(script_code) => {
+                if sig.hash_ty != SigHashType::All {
+                    return Err(Error::UnsupportedSigHashType(sig.hash_ty.as_u32()));
+                }
+                SighashComponents::new(self).sighash_all(input, &script_code, prevout.value)
+            }
+            None => self.signature_hash(input_index, &script_pubkey, sig.hash_ty.as_u32())
+        };
+
+        let msg = try!(Message::from_slice(&sighash[..]));
+        try!(secp.verify(&msg, &sig.sig, pubkey));
+        Ok(())
+    }
+
+    /// Returns the index of the input spending `outref`, if any
+    pub fn input_index_for(&self, outref: &TxOutRef) -> Option<usize> {
+        self.input.iter().position(|input| {
+            input.prev_hash == outref.txid && input.prev_index as usize == outref.index
+        })
+    }
+
+    /// Whether the transaction is a coinbase, i.e. has a single input which
+    /// spends the null output reference
+    pub fn is_coin_base(&self) -> bool {
+        self.input.len() == 1 && TxOutRef {
+            txid: self.input[0].prev_hash,
+            index: self.input[0].prev_index as usize
+        }.is_null()
+    }
+
+    /// Whether the transaction signals replaceability under BIP125, i.e. has an
+    /// input with a sequence number below `0xfffffffe`
+    pub fn is_explicitly_rbf(&self) -> bool {
+        self.input.iter().any(|input| input.sequence < 0xfffffffe)
+    }
+
+    /// Whether the lock time is enforced, i.e. some input has a sequence number
+    /// other than `0xffffffff`. If every input is final the lock time is ignored.
+    pub fn is_lock_time_enabled(&self) -> bool {
+        self.input.iter().any(|input| input.sequence < 0xffffffff)
+    }
+
+    /// Sums the values of all outputs, failing with `Error::ValueOverflow` if the
+    /// total exceeds the money supply
+    pub fn total_output_value(&self) -> Result<u64, Error> {
+        let mut total = 0u64;
+        for output in &self.output {
+            total = match total.checked_add(output.value) {
+                Some(sum) if constants::is_valid_money_range(sum) => sum,
+                _ => return Err(Error::ValueOverflow),
+            };
+        }
+        Ok(total)
+    }
+
+    /// Iterates over the outputs, yielding each output's index together with
+    /// the address it pays to, or `None` if it has no address form
+    pub fn addresses(&self, network: Network) -> OutputAddresses {
+        OutputAddresses {
+            outputs: self.output.iter().enumerate(),
+            network: network,
+        }
+    }
+
+    /// Gets the consensus-serialized size of this transaction, including any witness
+    #[inline]
+    pub fn get_size(&self) -> usize {
+        serialized_size(self)
+    }
+
     /// Gets the "weight" of this transaction, as defined by BIP141. For transactions with an empty
     /// witness, this is simply the consensus-serialized size times 4. For transactions with a
     /// witness, this is the non-witness consensus-serialized size multiplied by 3 plus the
@@ -218,22 +454,15 @@ impl Transaction {
         let mut input_weight = 0;
         let mut inputs_with_witnesses = 0;
         for input in &self.input {
-            input_weight += 4*(32 + 4 + 4 + // outpoint (32+4) + nSequence
-                VarInt(input.script_sig.len() as u64).encoded_length() +
-                input.script_sig.len() as u64);
+            input_weight += 4 * input.size() as u64;
             if !input.witness.is_empty() {
                 inputs_with_witnesses += 1;
-                input_weight += VarInt(input.witness.len() as u64).encoded_length();
-                for elem in &input.witness {
-                    input_weight += VarInt(elem.len() as u64).encoded_length() + elem.len() as u64;
-                }
+                input_weight += input.witness_size() as u64;
             }
         }
         let mut output_size = 0;
         for output in &self.output {
-            output_size += 8 + // value
-                VarInt(output.script_pubkey.len() as u64).encoded_length() +
-                output.script_pubkey.len() as u64;
+            output_size += output.size() as u64;
         }
         let non_input_size =
         // version:
@@ -251,6 +480,13 @@ impl Transaction {
         }
     }
 
+    /// Gets the virtual size of this transaction, its weight divided by four and
+    /// rounded up
+    #[inline]
+    pub fn get_vsize(&self) -> usize {
+        ((self.get_weight() + 3) / 4) as usize
+    }
+
     #[cfg(feature="bitcoinconsensus")]
     /// Verify that this transaction is able to spend some outputs of spent transactions
     pub fn verify (&self, spent : &HashMap<Sha256dHash, Transaction>) -> Result<(), script::Error> {
@@ -272,6 +508,100 @@ impl Transaction {
             Err(script::Error::SerializationError)
         }
     }
+
+    #[cfg(feature="bitcoinconsensus")]
+    /// Verify that this transaction is able to spend its inputs, looking up each
+    /// spent output through the `spent` closure rather than a map of full transactions
+    pub fn verify_with<S>(&self, spent: S) -> Result<(), script::Error>
+        where S: Fn(&TxOutRef) -> Option<TxOut>
+    {
+        self.verify_with_flags(spent, script::VERIFY_CONSENSUS)
+    }
+
+    #[cfg(feature="bitcoinconsensus")]
+    /// Like `verify_with`, but enforcing `flags` rather than the consensus rules,
+    /// see `Script::verify_with_flags`
+    pub fn verify_with_flags<S>(&self, spent: S, flags: script::VerifyFlags) -> Result<(), script::Error>
+        where S: Fn(&TxOutRef) -> Option<TxOut>
+    {
+        let tx = match serialize(&*self) {
+            Ok(tx) => tx,
+            Err(_) => return Err(script::Error::SerializationError)
+        };
+        for (idx, input) in self.input.iter().enumerate() {
+            let outref = TxOutRef { txid: input.prev_hash, index: input.prev_index as usize };
+            match spent(&outref) {
+                Some(output) => try!(output.script_pubkey.verify_with_flags(idx, output.value, tx.as_slice(), flags)),
+                None => return Err(script::Error::UnknownSpentOutput(outref))
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Iterator over the addresses of a transaction's outputs, see `Transaction::addresses`
+pub struct OutputAddresses<'a> {
+    outputs: iter::Enumerate<slice::Iter<'a, TxOut>>,
+    network: Network,
+}
+
+impl<'a> Iterator for OutputAddresses<'a> {
+    type Item = (usize, Option<Address>);
+
+    fn next(&mut self) -> Option<(usize, Option<Address>)> {
+        let network = self.network;
+        self.outputs.next().map(|(n, out)| (n, Address::from_script(&out.script_pubkey, network)))
+    }
+}
+
+/// An object which can be used to construct a transaction piece by piece
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TransactionBuilder(Transaction);
+
+impl TransactionBuilder {
+    /// Creates a new builder for a version 1 transaction with no inputs,
+    /// no outputs and a zero locktime
+    pub fn new() -> TransactionBuilder {
+        TransactionBuilder(Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![],
+            output: vec![],
+        })
+    }
+
+    /// Sets the protocol version
+    pub fn version(mut self, version: u32) -> TransactionBuilder {
+        self.0.version = version;
+        self
+    }
+
+    /// Sets the locktime
+    pub fn lock_time(mut self, lock_time: u32) -> TransactionBuilder {
+        self.0.lock_time = lock_time;
+        self
+    }
+
+    /// Appends an input
+    pub fn add_input(mut self, input: TxIn) -> TransactionBuilder {
+        self.0.input.push(input);
+        self
+    }
+
+    /// Appends an output
+    pub fn add_output(mut self, output: TxOut) -> TransactionBuilder {
+        self.0.output.push(output);
+        self
+    }
+
+    /// Converts the `TransactionBuilder` into the finished `Transaction`
+    pub fn build(self) -> Transaction {
+        self.0
+    }
+}
+
+impl Default for TransactionBuilder {
+    fn default() -> TransactionBuilder { TransactionBuilder::new() }
 }
 
 impl BitcoinHash for Transaction {
@@ -301,7 +631,7 @@ impl<D: SimpleDecoder> ConsensusDecodable<D> for TxIn {
             prev_index: try!(ConsensusDecodable::consensus_decode(d)),
             script_sig: try!(ConsensusDecodable::consensus_decode(d)),
             sequence: try!(ConsensusDecodable::consensus_decode(d)),
-            witness: vec![],
+            witness: Witness::new(),
         })
     }
 }
@@ -332,10 +662,38 @@ impl<S: SimpleEncoder> ConsensusEncodable<S> for Transaction {
     }
 }
 
+/// The smallest possible encoding of an input: outpoint, empty script and sequence
+const TXIN_MIN_SIZE: u64 = 32 + 4 + 1 + 4;
+/// The smallest possible encoding of an output: value and empty script
+const TXOUT_MIN_SIZE: u64 = 8 + 1;
+
+/// Decodes a vector of inputs or outputs, each taking at least `min_size` bytes
+/// on the wire. Since the count is untrusted, counts whose elements could not
+/// fit in `MAX_VEC_SIZE` bytes are rejected, and rather than allocating for the
+/// whole count up front the vector only grows as elements are actually read.
+fn decode_bounded_vec<D, T>(d: &mut D, min_size: u64) -> Result<Vec<T>, D::Error>
+    where D: SimpleDecoder, T: ConsensusDecodable<D>
+{
+    let VarInt(len): VarInt = try!(ConsensusDecodable::consensus_decode(d));
+    if len.saturating_mul(min_size) > MAX_VEC_SIZE as u64 {
+        return Err(d.error(format!("tried to allocate vec of {} elements of at least {} bytes (max {} bytes)",
+                                   len, min_size, MAX_VEC_SIZE)));
+    }
+    let max_items = d.limits().max_vec_items;
+    if len > max_items as u64 {
+        return Err(d.error(format!("tried to decode vec of {} elements (max {})", len, max_items)));
+    }
+    let mut ret = vec![];
+    for _ in 0..len {
+        ret.push(try!(ConsensusDecodable::consensus_decode(d)));
+    }
+    Ok(ret)
+}
+
 impl<D: SimpleDecoder> ConsensusDecodable<D> for Transaction {
     fn consensus_decode(d: &mut D) -> Result<Transaction, D::Error> {
         let version: u32 = try!(ConsensusDecodable::consensus_decode(d));
-        let input: Vec<TxIn> = try!(ConsensusDecodable::consensus_decode(d));
+        let input: Vec<TxIn> = try!(decode_bounded_vec(d, TXIN_MIN_SIZE));
         // segwit
         if input.is_empty() {
             let segwit_flag: u8 = try!(ConsensusDecodable::consensus_decode(d));
@@ -351,8 +709,8 @@ impl<D: SimpleDecoder> ConsensusDecodable<D> for Transaction {
                 }
                 // BIP144 input witnesses
                 1 => {
-                    let mut input: Vec<TxIn> = try!(ConsensusDecodable::consensus_decode(d));
-                    let output: Vec<TxOut> = try!(ConsensusDecodable::consensus_decode(d));
+                    let mut input: Vec<TxIn> = try!(decode_bounded_vec(d, TXIN_MIN_SIZE));
+                    let output: Vec<TxOut> = try!(decode_bounded_vec(d, TXOUT_MIN_SIZE));
                     for txin in input.iter_mut() {
                         txin.witness = try!(ConsensusDecodable::consensus_decode(d));
                     }
@@ -373,7 +731,7 @@ impl<D: SimpleDecoder> ConsensusDecodable<D> for Transaction {
             Ok(Transaction {
                 version: version,
                 input: input,
-                output: try!(ConsensusDecodable::consensus_decode(d)),
+                output: try!(decode_bounded_vec(d, TXOUT_MIN_SIZE)),
                 lock_time: try!(ConsensusDecodable::consensus_decode(d)),
             })
         }
@@ -435,15 +793,43 @@ impl SigHashType {
 }
 
 
+/// Serialization of a transaction as its consensus hex string, as in the `hex`
+/// field of Bitcoin Core's RPC, rather than the default structure of fields
+pub mod serde_hex {
+    use serde;
+    use serialize::hex::FromHex;
+
+    use network::serialize::{deserialize as consensus_deserialize, serialize_hex};
+    use super::Transaction;
+
+    /// Serializes a transaction as a hex string
+    pub fn serialize<S>(tx: &Transaction, s: &mut S) -> Result<(), S::Error>
+        where S: serde::Serializer
+    {
+        // unwrap is safe as serializing into a vector cannot fail
+        s.visit_str(&serialize_hex(tx).unwrap())
+    }
+
+    /// Deserializes a transaction from a hex string
+    pub fn deserialize<D>(d: &mut D) -> Result<Transaction, D::Error>
+        where D: serde::Deserializer
+    {
+        let hex: String = try!(serde::Deserialize::deserialize(d));
+        let bytes = try!(hex.from_hex().map_err(|_| serde::de::Error::syntax("bad hex")));
+        consensus_deserialize(&bytes).map_err(|_| serde::de::Error::syntax("bad transaction"))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use strason;
 
-    use super::{Transaction, TxIn};
+    use super::{Transaction, TransactionBuilder, TxIn, TxOut, TxOutRef};
 
+    use blockdata::constants::genesis_block;
     use blockdata::script::Script;
     use network::serialize::BitcoinHash;
-    use network::serialize::{serialize, deserialize};
+    use network::serialize::{serialize, serialized_size, deserialize};
     use util::hash::Sha256dHash;
     use util::misc::hex_bytes;
 
@@ -453,6 +839,189 @@ mod tests {
         assert!(txin.is_ok());
     }
 
+    #[test]
+    fn test_txoutref_null() {
+        let null = TxOutRef::null();
+        assert_eq!(null.txid, Default::default());
+        assert_eq!(null.index, 0xFFFFFFFF);
+        assert!(null.is_null());
+
+        assert!(!TxOutRef { txid: Default::default(), index: 0 }.is_null());
+        let txid = Sha256dHash::from_data(&[]);
+        assert!(!TxOutRef { txid: txid, index: 0xFFFFFFFF }.is_null());
+    }
+
+    #[test]
+    fn test_txout_dust() {
+        use serialize::hex::FromHex;
+        use super::DUST_RELAY_TX_FEE;
+
+        let p2wpkh = hex_script!("00141d0f172a0ecb48aee1be1f2687d2963ae33f71a1");
+        assert_eq!(TxOut::new(0, p2wpkh.clone()).dust_threshold(DUST_RELAY_TX_FEE), 294);
+        assert!(TxOut::new(293, p2wpkh.clone()).is_dust(DUST_RELAY_TX_FEE));
+        assert!(!TxOut::new(294, p2wpkh.clone()).is_dust(DUST_RELAY_TX_FEE));
+        assert!(!TxOut::new(10000, p2wpkh.clone()).is_dust(DUST_RELAY_TX_FEE));
+        assert!(TxOut::new(10000, p2wpkh.clone()).is_dust(200000));
+        // the fee is rounded up
+        assert_eq!(TxOut::new(0, p2wpkh.clone()).dust_threshold(1001), 99);
+
+        let p2pkh = hex_script!("76a914162c5ea71c0b23f5b9022ef047c4a86470a5b07088ac");
+        assert_eq!(TxOut::new(0, p2pkh).dust_threshold(DUST_RELAY_TX_FEE), 546);
+
+        let op_return = hex_script!("6a0401020304");
+        assert!(!TxOut::new(0, op_return).is_dust(DUST_RELAY_TX_FEE));
+        // only OP_RETURN is exempt, not other unspendable scripts
+        let op_verif = hex_script!("65");
+        assert_eq!(TxOut::new(0, op_verif).dust_threshold(DUST_RELAY_TX_FEE), 474);
+    }
+
+    #[test]
+    fn test_input_index_for() {
+        let tx: Transaction = deserialize(&hex_bytes("01000000000102fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f00000000494830450221008b9d1dc26ba6a9cb62127b02742fa9d754cd3bebf337f7a55d114c8e5cdd30be022040529b194ba3f9281a99f2b1c0a19c0489bc22ede944ccf4ecbab4cc618ef3ed01eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac000247304402203609e17b84f6a7d30c80bfa610b5b4542f32a8a0d5447a12fb1366d7f01cc44a0220573a954c4518331561406f90300e8f3358f51928d43c212a8caed02de67eebee0121025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee635711000000").unwrap()).unwrap();
+
+        for (i, input) in tx.input.iter().enumerate() {
+            let outref = TxOutRef { txid: input.prev_hash, index: input.prev_index as usize };
+            assert_eq!(tx.input_index_for(&outref), Some(i));
+        }
+        let outref = TxOutRef { txid: tx.input[1].prev_hash, index: 0 };
+        assert_eq!(tx.input_index_for(&outref), None);
+        assert_eq!(tx.input_index_for(&TxOutRef::null()), None);
+    }
+
+    #[test]
+    fn test_is_coin_base() {
+        use network::constants::Network;
+
+        let coinbase = genesis_block(Network::Bitcoin).txdata[0].clone();
+        assert!(coinbase.is_coin_base());
+
+        let tx: Transaction = deserialize(&hex_bytes("0100000001a15d57094aa7a21a28cb20b59aab8fc7d1149a3bdbcddba9c622e4f5f6a99ece010000006c493046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c0121033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52ffffffff0100e1f505000000001976a9140389035a9225b3839e2bbf32d826a1e222031fd888ac00000000").unwrap()).unwrap();
+        assert!(!tx.is_coin_base());
+    }
+
+    #[test]
+    fn test_verify_input_ecdsa() {
+        use secp256k1::Secp256k1;
+        use secp256k1::key::PublicKey;
+
+        use serialize::hex::FromHex;
+
+        use super::SigHashType;
+        use util::ecdsa::EcdsaSig;
+        use util::Error;
+
+        // The signed native P2WPKH example from BIP143, which spends a P2PK and a P2WPKH output
+        let tx: Transaction = deserialize(&hex_bytes("01000000000102fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f00000000494830450221008b9d1dc26ba6a9cb62127b02742fa9d754cd3bebf337f7a55d114c8e5cdd30be022040529b194ba3f9281a99f2b1c0a19c0489bc22ede944ccf4ecbab4cc618ef3ed01eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac000247304402203609e17b84f6a7d30c80bfa610b5b4542f32a8a0d5447a12fb1366d7f01cc44a0220573a954c4518331561406f90300e8f3358f51928d43c212a8caed02de67eebee0121025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee635711000000").unwrap()).unwrap();
+        let secp = Secp256k1::new();
+
+        let p2pk = TxOut {
+            value: 625000000,
+            script_pubkey: hex_script!("2103c9f4836b9a4f77fc0d81f7bcb01b7f1b35916864b9476c241ce9fc198bd25432ac")
+        };
+        let p2pk_key = PublicKey::from_slice(&secp, &hex_bytes("03c9f4836b9a4f77fc0d81f7bcb01b7f1b35916864b9476c241ce9fc198bd25432").unwrap()).unwrap();
+        let p2pk_sig = EcdsaSig::from_slice(&secp, &hex_bytes("30450221008b9d1dc26ba6a9cb62127b02742fa9d754cd3bebf337f7a55d114c8e5cdd30be022040529b194ba3f9281a99f2b1c0a19c0489bc22ede944ccf4ecbab4cc618ef3ed01").unwrap()).unwrap();
+        assert!(tx.verify_input_ecdsa(&secp, 0, &p2pk, &p2pk_key, &p2pk_sig).is_ok());
+
+        let p2wpkh = TxOut {
+            value: 600000000,
+            script_pubkey: hex_script!("00141d0f172a0ecb48aee1be1f2687d2963ae33f71a1")
+        };
+        let p2wpkh_key = PublicKey::from_slice(&secp, &hex_bytes("025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee6357").unwrap()).unwrap();
+        let p2wpkh_sig = EcdsaSig::from_slice(&secp, &hex_bytes("304402203609e17b84f6a7d30c80bfa610b5b4542f32a8a0d5447a12fb1366d7f01cc44a0220573a954c4518331561406f90300e8f3358f51928d43c212a8caed02de67eebee01").unwrap()).unwrap();
+        assert!(tx.verify_input_ecdsa(&secp, 1, &p2wpkh, &p2wpkh_key, &p2wpkh_sig).is_ok());
+
+        // wrong input, wrong amount, wrong key
+        assert!(tx.verify_input_ecdsa(&secp, 0, &p2wpkh, &p2wpkh_key, &p2wpkh_sig).is_err());
+        let mut wrong_value = p2wpkh.clone();
+        wrong_value.value -= 1;
+        assert!(tx.verify_input_ecdsa(&secp, 1, &wrong_value, &p2wpkh_key, &p2wpkh_sig).is_err());
+        assert!(tx.verify_input_ecdsa(&secp, 1, &p2wpkh, &p2pk_key, &p2wpkh_sig).is_err());
+
+        let mut single = p2wpkh_sig;
+        single.hash_ty = SigHashType::Single;
+        match tx.verify_input_ecdsa(&secp, 1, &p2wpkh, &p2wpkh_key, &single) {
+            Err(Error::UnsupportedSigHashType(3)) => {},
+            x => panic!("unexpected result {:?}", x),
+        }
+
+        match tx.verify_input_ecdsa(&secp, 2, &p2wpkh, &p2wpkh_key, &p2wpkh_sig) {
+            Err(Error::InputIndexOutOfRange(2)) => {},
+            x => panic!("unexpected result {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_stream_decoder() {
+        use std::io::Cursor;
+        use network::serialize::StreamDecoder;
+
+        let tx_bytes = hex_bytes("0100000001a15d57094aa7a21a28cb20b59aab8fc7d1149a3bdbcddba9c622e4f5f6a99ece010000006c493046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c0121033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52ffffffff0100e1f505000000001976a9140389035a9225b3839e2bbf32d826a1e222031fd888ac00000000").unwrap();
+        let tx: Transaction = deserialize(&tx_bytes).unwrap();
+        let mut other = tx.clone();
+        other.lock_time = 1;
+
+        let mut stream = tx_bytes.clone();
+        stream.extend(serialize(&other).unwrap());
+        stream.extend(&tx_bytes);
+
+        let decoded: Result<Vec<Transaction>, _> = StreamDecoder::new(Cursor::new(&stream[..])).collect();
+        assert_eq!(decoded.unwrap(), vec![tx.clone(), other, tx.clone()]);
+
+        let mut decoder = StreamDecoder::<_, Transaction>::new(Cursor::new(&stream[..stream.len() - 1]));
+        assert_eq!(decoder.next().unwrap().unwrap(), tx);
+        assert!(decoder.next().unwrap().is_ok());
+        assert!(decoder.next().unwrap().is_err());
+        assert!(decoder.next().is_none());
+
+        assert!(StreamDecoder::<_, Transaction>::new(Cursor::new(&[][..])).next().is_none());
+    }
+
+    #[test]
+    fn test_deserialize_with_limits() {
+        use network::serialize::{deserialize_with_limits, DecodeLimits};
+
+        let tx_bytes = hex_bytes("0100000001a15d57094aa7a21a28cb20b59aab8fc7d1149a3bdbcddba9c622e4f5f6a99ece010000006c493046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c0121033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52ffffffff0100e1f505000000001976a9140389035a9225b3839e2bbf32d826a1e222031fd888ac00000000").unwrap();
+        let tx: Transaction = deserialize(&tx_bytes).unwrap();
+        let limits = DecodeLimits::default();
+        assert_eq!(deserialize_with_limits::<Transaction>(&tx_bytes, limits).unwrap(), tx);
+
+        let exact = DecodeLimits { max_total_bytes: tx_bytes.len(), ..limits };
+        assert_eq!(deserialize_with_limits::<Transaction>(&tx_bytes, exact).unwrap(), tx);
+        let small = DecodeLimits { max_total_bytes: tx_bytes.len() - 1, ..limits };
+        assert!(deserialize_with_limits::<Transaction>(&tx_bytes, small).is_err());
+
+        // the scriptSig is 108 bytes
+        let scripts = DecodeLimits { max_script_size: 108, ..limits };
+        assert!(deserialize_with_limits::<Transaction>(&tx_bytes, scripts).is_ok());
+        let scripts = DecodeLimits { max_script_size: 107, ..limits };
+        assert!(deserialize_with_limits::<Transaction>(&tx_bytes, scripts).is_err());
+
+        let items = DecodeLimits { max_vec_items: 0, ..limits };
+        assert!(deserialize_with_limits::<Transaction>(&tx_bytes, items).is_err());
+    }
+
+    #[test]
+    fn test_transaction_oversized_counts() {
+        use util::Error;
+
+        // version, then a count of 0xffffffff inputs and nothing else
+        let tx: Result<Transaction, _> = deserialize(&hex_bytes("01000000feffffffff").unwrap());
+        match tx {
+            Err(Error::Detail(ref s, _)) => assert!(s.contains("allocate")),
+            x => panic!("unexpected result {:?}", x),
+        }
+        // the same for the outputs of a segwit transaction
+        let tx: Result<Transaction, _> = deserialize(&hex_bytes("010000000001\
+                                                                  01a15d57094aa7a21a28cb20b59aab8fc7d1149a3bdbcddba9c622e4f5f6a99ece0100000000ffffffff\
+                                                                  ffffffffffffffffff").unwrap());
+        match tx {
+            Err(Error::Detail(ref s, _)) => assert!(s.contains("allocate")),
+            x => panic!("unexpected result {:?}", x),
+        }
+        // a plausible count with no data behind it fails without allocating for it
+        assert!(deserialize::<Transaction>(&hex_bytes("01000000fe00000100").unwrap()).is_err());
+    }
+
     #[test]
     fn test_transaction() {
         let hex_tx = hex_bytes("0100000001a15d57094aa7a21a28cb20b59aab8fc7d1149a3bdbcddba9c622e4f5f6a99ece010000006c493046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c0121033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52ffffffff0100e1f505000000001976a9140389035a9225b3839e2bbf32d826a1e222031fd888ac00000000").unwrap();
@@ -476,6 +1045,171 @@ mod tests {
         assert_eq!(realtx.get_weight(), 193*4);
     }
 
+    #[test]
+    fn test_transaction_builder() {
+        let hex_tx = hex_bytes("0100000001a15d57094aa7a21a28cb20b59aab8fc7d1149a3bdbcddba9c622e4f5f6a99ece010000006c493046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c0121033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52ffffffff0100e1f505000000001976a9140389035a9225b3839e2bbf32d826a1e222031fd888ac00000000").unwrap();
+        let realtx: Transaction = deserialize(&hex_tx).unwrap();
+
+        let tx = TransactionBuilder::new()
+            .add_input(TxIn::new(realtx.input[0].prev_hash, 1, realtx.input[0].script_sig.clone(), 0xffffffff))
+            .add_output(TxOut::new(100000000, Script::from(hex_bytes("76a9140389035a9225b3839e2bbf32d826a1e222031fd888ac").unwrap())))
+            .build();
+        assert_eq!(tx, realtx);
+        assert_eq!(tx.txid().be_hex_string(),
+                   "a6eab3c14ab5272a58a5ba91505ba1a4b6d7a3a9fcbd187b6cd99a7b6d548cb7".to_string());
+
+        let tx = TransactionBuilder::new().version(2).lock_time(500000).build();
+        assert_eq!(tx.version, 2);
+        assert_eq!(tx.lock_time, 500000);
+        assert!(tx.input.is_empty() && tx.output.is_empty());
+    }
+
+    #[test]
+    fn test_txout_new_checked() {
+        use blockdata::constants::MAX_MONEY;
+        use util::Error;
+
+        assert_eq!(TxOut::new_checked(MAX_MONEY, Script::new()).unwrap(), TxOut::new(MAX_MONEY, Script::new()));
+        match TxOut::new_checked(MAX_MONEY + 1, Script::new()) {
+            Err(Error::ValueOverflow) => {},
+            x => panic!("unexpected result {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_total_output_value() {
+        use blockdata::constants::COIN_VALUE;
+        use util::Error;
+
+        let tx = TransactionBuilder::new()
+            .add_output(TxOut::new(5 * COIN_VALUE, Script::new()))
+            .add_output(TxOut::new(7 * COIN_VALUE, Script::new()))
+            .build();
+        assert_eq!(tx.total_output_value().unwrap(), 12 * COIN_VALUE);
+        assert_eq!(TransactionBuilder::new().build().total_output_value().unwrap(), 0);
+
+        // Exactly the money supply is fine, one more satoshi is not
+        let tx = TransactionBuilder::new()
+            .add_output(TxOut::new(21_000_000 * COIN_VALUE, Script::new()))
+            .build();
+        assert_eq!(tx.total_output_value().unwrap(), 21_000_000 * COIN_VALUE);
+        let tx = TransactionBuilder::new()
+            .add_output(TxOut::new(21_000_000 * COIN_VALUE, Script::new()))
+            .add_output(TxOut::new(1, Script::new()))
+            .build();
+        match tx.total_output_value() {
+            Err(Error::ValueOverflow) => {},
+            x => panic!("unexpected result {:?}", x),
+        }
+
+        // Sum past the u64 range
+        let tx = TransactionBuilder::new()
+            .add_output(TxOut::new(u64::max_value(), Script::new()))
+            .add_output(TxOut::new(u64::max_value(), Script::new()))
+            .build();
+        match tx.total_output_value() {
+            Err(Error::ValueOverflow) => {},
+            x => panic!("unexpected result {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_serde_hex() {
+        use strason::Json;
+        use strason::object::{Deserializer, Serializer};
+
+        let hex = "0100000001a15d57094aa7a21a28cb20b59aab8fc7d1149a3bdbcddba9c622e4f5f6a99ece010000006c493046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c0121033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52ffffffff0100e1f505000000001976a9140389035a9225b3839e2bbf32d826a1e222031fd888ac00000000";
+        let tx: Transaction = deserialize(&hex_bytes(hex).unwrap()).unwrap();
+
+        let mut ser = Serializer::new();
+        super::serde_hex::serialize(&tx, &mut ser).unwrap();
+        let json = ser.unwrap();
+        assert_eq!(json.string(), Some(hex));
+        let des = super::serde_hex::deserialize(&mut Deserializer::new(json)).unwrap();
+        assert_eq!(des, tx);
+
+        let bad = Json::from_str("\"0100\"").unwrap();
+        assert!(super::serde_hex::deserialize(&mut Deserializer::new(bad)).is_err());
+        let bad = Json::from_str("\"zz\"").unwrap();
+        assert!(super::serde_hex::deserialize(&mut Deserializer::new(bad)).is_err());
+    }
+
+    #[test]
+    fn test_rbf_and_lock_time_signalling() {
+        let input = |sequence| TxIn::new(Default::default(), 0, Script::new(), sequence);
+
+        // all inputs final: the lock time has no effect
+        let tx = TransactionBuilder::new()
+            .lock_time(500_000)
+            .add_input(input(0xffffffff))
+            .add_input(input(0xffffffff))
+            .build();
+        assert!(!tx.is_lock_time_enabled());
+        assert!(!tx.is_explicitly_rbf());
+
+        // 0xfffffffe enables the lock time without opting into RBF
+        let tx = TransactionBuilder::new().add_input(input(0xffffffff)).add_input(input(0xfffffffe)).build();
+        assert!(tx.is_lock_time_enabled());
+        assert!(!tx.is_explicitly_rbf());
+
+        // a single lower sequence opts the whole transaction in
+        let tx = TransactionBuilder::new().add_input(input(0xffffffff)).add_input(input(0xfffffffd)).build();
+        assert!(tx.is_lock_time_enabled());
+        assert!(tx.is_explicitly_rbf());
+
+        assert!(!TransactionBuilder::new().build().is_lock_time_enabled());
+    }
+
+    #[test]
+    fn test_transaction_debug() {
+        let hex_tx = hex_bytes("0100000001a15d57094aa7a21a28cb20b59aab8fc7d1149a3bdbcddba9c622e4f5f6a99ece010000006c493046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c0121033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52ffffffff0100e1f505000000001976a9140389035a9225b3839e2bbf32d826a1e222031fd888ac00000000").unwrap();
+        let tx: Transaction = deserialize(&hex_tx).unwrap();
+
+        assert_eq!(format!("{:?}", tx),
+                   "Transaction { txid: a6eab3c14ab5272a58a5ba91505ba1a4b6d7a3a9fcbd187b6cd99a7b6d548cb7, \
+                    version: 1, lock_time: 0, inputs: 1, outputs: 1 }");
+        let verbose = format!("{:#?}", tx);
+        assert!(verbose.contains("a6eab3c14ab5272a58a5ba91505ba1a4b6d7a3a9fcbd187b6cd99a7b6d548cb7"));
+        assert!(verbose.contains("script_pubkey"));
+    }
+
+    #[test]
+    fn test_addresses() {
+        use std::str::FromStr;
+        use network::constants::Network;
+        use util::address::Address;
+
+        let p2wpkh = Address::from_str("bc1qvzvkjn4q3nszqxrv3nraga2r822xjty3ykvkuw").unwrap();
+        let tx = TransactionBuilder::new()
+            .add_output(TxOut::new(1000, p2wpkh.script_pubkey()))
+            .add_output(TxOut::new(0, Script::from(hex_bytes("6a0401020304").unwrap())))
+            .build();
+        let addresses: Vec<_> = tx.addresses(Network::Bitcoin).collect();
+        assert_eq!(addresses, vec![(0, Some(p2wpkh)), (1, None)]);
+    }
+
+    #[test]
+    fn test_txin_txout_size() {
+        use blockdata::witness::Witness;
+
+        // p2pkh output
+        let out = TxOut::new(100000000, Script::from(hex_bytes("76a9140389035a9225b3839e2bbf32d826a1e222031fd888ac").unwrap()));
+        assert_eq!(out.size(), serialize(&out).unwrap().len());
+        assert_eq!(out.size(), 34);
+
+        // p2wpkh input
+        let mut input = TxIn::new(Default::default(), 0, Script::new(), 0xffffffff);
+        input.witness = Witness::from(vec![vec![0x30; 72], vec![0x02; 33]]);
+        assert_eq!(input.size(), serialize(&input).unwrap().len());
+        assert_eq!(input.size(), 41);
+        assert_eq!(input.witness_size(), serialize(&input.witness).unwrap().len());
+        assert_eq!(input.witness_size(), 108);
+
+        // weight of a 1-in 1-out segwit transaction from the parts
+        let tx = TransactionBuilder::new().add_input(input.clone()).add_output(out.clone()).build();
+        assert_eq!(tx.get_weight(), 4 * (4 + 1 + 41 + 1 + 34 + 4) as u64 + 2 + 108);
+    }
+
     #[test]
     fn test_ntxid() {
         let hex_tx = hex_bytes("0100000001a15d57094aa7a21a28cb20b59aab8fc7d1149a3bdbcddba9c622e4f5f6a99ece010000006c493046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c0121033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52ffffffff0100e1f505000000001976a9140389035a9225b3839e2bbf32d826a1e222031fd888ac00000000").unwrap();
@@ -529,6 +1263,8 @@ mod tests {
         assert_eq!(tx.bitcoin_hash().be_hex_string(), "d6ac4a5e61657c4c604dcde855a1db74ec6b3e54f32695d72c5e11c7761ea1b4");
         assert_eq!(tx.txid().be_hex_string(), "9652aa62b0e748caeec40c4cb7bc17c6792435cc3dfe447dd1ca24f912a1c6ec");
         assert_eq!(tx.get_weight(), 2718);
+        assert_eq!(tx.get_size(), hex_tx.len());
+        assert_eq!(serialized_size(&tx), serialize(&tx).unwrap().len());
 
         // non-segwit tx from my mempool
         let hex_tx = hex_bytes(
@@ -885,6 +1621,7 @@ mod tests {
         use serialize::hex::FromHex;
         use std::collections::HashMap;
         use blockdata::script;
+        use blockdata::witness::Witness;
         // a random recent segwit transaction from blockchain using both old and segwit inputs
         let mut spending: Transaction = deserialize("020000000001031cfbc8f54fbfa4a33a30068841371f80dbfe166211242213188428f437445c91000000006a47304402206fbcec8d2d2e740d824d3d36cc345b37d9f65d665a99f5bd5c9e8d42270a03a8022013959632492332200c2908459547bf8dbf97c65ab1a28dec377d6f1d41d3d63e012103d7279dfb90ce17fe139ba60a7c41ddf605b25e1c07a4ddcb9dfef4e7d6710f48feffffff476222484f5e35b3f0e43f65fc76e21d8be7818dd6a989c160b1e5039b7835fc00000000171600140914414d3c94af70ac7e25407b0689e0baa10c77feffffffa83d954a62568bbc99cc644c62eb7383d7c2a2563041a0aeb891a6a4055895570000000017160014795d04cc2d4f31480d9a3710993fbd80d04301dffeffffff06fef72f000000000017a91476fd7035cd26f1a32a5ab979e056713aac25796887a5000f00000000001976a914b8332d502a529571c6af4be66399cd33379071c588ac3fda0500000000001976a914fc1d692f8de10ae33295f090bea5fe49527d975c88ac522e1b00000000001976a914808406b54d1044c429ac54c0e189b0d8061667e088ac6eb68501000000001976a914dfab6085f3a8fb3e6710206a5a959313c5618f4d88acbba20000000000001976a914eb3026552d7e3f3073457d0bee5d4757de48160d88ac0002483045022100bee24b63212939d33d513e767bc79300051f7a0d433c3fcf1e0e3bf03b9eb1d70220588dc45a9ce3a939103b4459ce47500b64e23ab118dfc03c9caa7d6bfc32b9c601210354fd80328da0f9ae6eef2b3a81f74f9a6f66761fadf96f1d1d22b1fd6845876402483045022100e29c7e3a5efc10da6269e5fc20b6a1cb8beb92130cc52c67e46ef40aaa5cac5f0220644dd1b049727d991aece98a105563416e10a5ac4221abac7d16931842d5c322012103960b87412d6e169f30e12106bdf70122aabb9eb61f455518322a18b920a4dfa887d30700"
             .from_hex().unwrap().as_slice()).unwrap();
@@ -901,13 +1638,30 @@ mod tests {
         spent.insert(spent3.txid(), spent3);
 
         spending.verify(&spent).unwrap();
+        spending.verify_with(|outref| spent.get(&outref.txid)
+                                           .and_then(|tx| tx.output.get(outref.index))
+                                           .cloned()).unwrap();
+
+        // test that we get a failure if a prevout is missing
+        match spending.verify_with(|_| None).err().unwrap() {
+            script::Error::UnknownSpentOutput(outref) => assert_eq!(outref.txid, spending.input[0].prev_hash),
+            _ => panic!("Wrong error type"),
+        }
 
         // test that we get a failure if we corrupt a signature
-        spending.input[1].witness[0][10] = 42;
+        let mut witness = spending.input[1].witness.to_vec();
+        witness[0][10] = 42;
+        spending.input[1].witness = Witness::from(witness);
         match spending.verify(&spent).err().unwrap() {
             script::Error::BitcoinConsensus(_) => {},
             _ => panic!("Wrong error type"),
         }
+        match spending.verify_with(|outref| spent.get(&outref.txid)
+                                                 .and_then(|tx| tx.output.get(outref.index))
+                                                 .cloned()).err().unwrap() {
+            script::Error::BitcoinConsensus(_) => {},
+            _ => panic!("Wrong error type"),
+        }
     }
 }
 
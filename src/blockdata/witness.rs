@@ -0,0 +1,285 @@
+// Rust Bitcoin Library
+// Written in 2018 by
+//     The Rust Bitcoin developers
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Witness
+//!
+//! The segregated witness attached to a transaction input, as defined
+//! by BIP141. Witness elements are stored back to back in a single
+//! buffer, each preceded by its length as a `VarInt`, which is exactly
+//! how they appear on the wire.
+//!
+
+use std::fmt;
+use std::ops::Index;
+
+use serde;
+
+use network::encodable::{ConsensusDecodable, ConsensusEncodable, VarInt, MAX_VEC_SIZE};
+use network::serialize::{SimpleDecoder, SimpleEncoder};
+use util::endian;
+
+/// The witness stack of a transaction input
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub struct Witness {
+    /// Length-prefixed witness elements
+    content: Vec<u8>,
+    /// Number of elements in `content`
+    witness_elements: usize,
+    /// Offset into `content` of the last element's length prefix
+    last: usize,
+}
+
+/// Iterator over the elements of a witness
+pub struct Iter<'a> {
+    content: &'a [u8],
+    remaining: usize,
+}
+
+/// Appends `n` to `buf` encoded as a `VarInt`
+fn push_varint(buf: &mut Vec<u8>, n: u64) {
+    match n {
+        0...0xFC => buf.push(n as u8),
+        0xFD...0xFFFF => {
+            buf.push(0xFD);
+            buf.extend_from_slice(&endian::u16_to_array_le(n as u16));
+        }
+        0x10000...0xFFFFFFFF => {
+            buf.push(0xFE);
+            buf.extend_from_slice(&endian::u32_to_array_le(n as u32));
+        }
+        _ => {
+            buf.push(0xFF);
+            buf.extend_from_slice(&endian::u64_to_array_le(n));
+        }
+    }
+}
+
+/// Reads a `VarInt` written by `push_varint`, returning its value and encoded size
+fn read_varint(buf: &[u8]) -> (usize, usize) {
+    let width = match buf[0] {
+        0xFF => 8,
+        0xFE => 4,
+        0xFD => 2,
+        n => return (n as usize, 1),
+    };
+    let mut n = 0;
+    for i in 0..width {
+        n |= (buf[1 + i] as usize) << (8 * i);
+    }
+    (n, 1 + width)
+}
+
+impl Witness {
+    /// Creates a new empty witness
+    pub fn new() -> Witness { Witness::default() }
+
+    /// Number of elements in the witness
+    pub fn len(&self) -> usize { self.witness_elements }
+
+    /// Whether the witness has no elements
+    pub fn is_empty(&self) -> bool { self.witness_elements == 0 }
+
+    /// Appends an element to the witness
+    pub fn push(&mut self, data: &[u8]) {
+        self.last = self.content.len();
+        self.witness_elements += 1;
+        push_varint(&mut self.content, data.len() as u64);
+        self.content.extend_from_slice(data);
+    }
+
+    /// The last element of the witness, if any
+    pub fn last(&self) -> Option<&[u8]> {
+        if self.is_empty() {
+            None
+        } else {
+            let (len, prefix) = read_varint(&self.content[self.last..]);
+            let start = self.last + prefix;
+            Some(&self.content[start..start + len])
+        }
+    }
+
+    /// Iterates over the elements of the witness
+    pub fn iter(&self) -> Iter {
+        Iter {
+            content: &self.content,
+            remaining: self.witness_elements,
+        }
+    }
+
+    /// Copies the witness out into a vector of elements
+    pub fn to_vec(&self) -> Vec<Vec<u8>> {
+        self.iter().map(|elem| elem.to_vec()).collect()
+    }
+
+    /// The serialized size of the witness, including the element count
+    pub fn serialized_len(&self) -> u64 {
+        VarInt(self.witness_elements as u64).encoded_length() + self.content.len() as u64
+    }
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let (len, prefix) = read_varint(self.content);
+        let elem = &self.content[prefix..prefix + len];
+        self.content = &self.content[prefix + len..];
+        self.remaining -= 1;
+        Some(elem)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a> IntoIterator for &'a Witness {
+    type Item = &'a [u8];
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> { self.iter() }
+}
+
+impl Index<usize> for Witness {
+    type Output = [u8];
+
+    fn index(&self, index: usize) -> &[u8] {
+        self.iter().nth(index).expect("witness index out of bounds")
+    }
+}
+
+impl From<Vec<Vec<u8>>> for Witness {
+    fn from(elems: Vec<Vec<u8>>) -> Witness {
+        let mut ret = Witness::new();
+        for elem in &elems {
+            ret.push(elem);
+        }
+        ret
+    }
+}
+
+impl fmt::Debug for Witness {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(f.write_str("Witness(["));
+        for (n, elem) in self.iter().enumerate() {
+            if n > 0 {
+                try!(f.write_str(", "));
+            }
+            for ch in elem {
+                try!(write!(f, "{:02x}", ch));
+            }
+        }
+        f.write_str("])")
+    }
+}
+
+impl<S: SimpleEncoder> ConsensusEncodable<S> for Witness {
+    fn consensus_encode(&self, s: &mut S) -> Result<(), S::Error> {
+        try!(VarInt(self.witness_elements as u64).consensus_encode(s));
+        for &ch in &self.content {
+            try!(s.emit_u8(ch));
+        }
+        Ok(())
+    }
+}
+
+impl<D: SimpleDecoder> ConsensusDecodable<D> for Witness {
+    fn consensus_decode(d: &mut D) -> Result<Witness, D::Error> {
+        let VarInt(n): VarInt = try!(ConsensusDecodable::consensus_decode(d));
+        let max_items = d.limits().max_vec_items;
+        if n > max_items as u64 {
+            return Err(d.error(format!("tried to decode witness of {} elements (max {})", n, max_items)));
+        }
+        let mut ret = Witness::new();
+        for _ in 0..n {
+            let VarInt(len): VarInt = try!(ConsensusDecodable::consensus_decode(d));
+            let size = ret.content.len() as u64 + VarInt(len).encoded_length() + len;
+            if size > MAX_VEC_SIZE as u64 {
+                return Err(d.error(format!("tried to allocate witness of size {} (max {})", size, MAX_VEC_SIZE)));
+            }
+            ret.last = ret.content.len();
+            ret.witness_elements += 1;
+            push_varint(&mut ret.content, len);
+            for _ in 0..len {
+                ret.content.push(try!(d.read_u8()));
+            }
+        }
+        Ok(ret)
+    }
+}
+
+// User-facing serialization, as a list of elements
+impl serde::Serialize for Witness {
+    fn serialize<S>(&self, s: &mut S) -> Result<(), S::Error>
+        where S: serde::Serializer,
+    {
+        self.to_vec().serialize(s)
+    }
+}
+
+impl serde::Deserialize for Witness {
+    fn deserialize<D>(d: &mut D) -> Result<Witness, D::Error>
+        where D: serde::Deserializer
+    {
+        let elems: Vec<Vec<u8>> = try!(serde::Deserialize::deserialize(d));
+        Ok(Witness::from(elems))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Witness;
+
+    use network::serialize::{deserialize, serialize};
+
+    #[test]
+    fn witness_push_iter() {
+        let mut witness = Witness::new();
+        assert!(witness.is_empty());
+        assert_eq!(witness.last(), None);
+        assert_eq!(witness.iter().next(), None);
+
+        witness.push(&[]);
+        witness.push(&[1, 2, 3]);
+        witness.push(&[0xab; 300]);
+        assert_eq!(witness.len(), 3);
+        assert!(!witness.is_empty());
+        assert_eq!(witness.last(), Some(&[0xab; 300][..]));
+        assert_eq!(&witness[1], &[1, 2, 3][..]);
+
+        let elems: Vec<&[u8]> = witness.iter().collect();
+        assert_eq!(elems, vec![&[][..], &[1, 2, 3][..], &[0xab; 300][..]]);
+        assert_eq!(witness.to_vec(), vec![vec![], vec![1, 2, 3], vec![0xab; 300]]);
+        assert_eq!(Witness::from(witness.to_vec()), witness);
+    }
+
+    #[test]
+    fn witness_consensus_round_trip() {
+        let witness = Witness::from(vec![vec![0x30; 71], vec![0x02; 33], vec![0xcd; 0x10000]]);
+        let ser = serialize(&witness).unwrap();
+        assert_eq!(ser.len() as u64, witness.serialized_len());
+        // Same encoding as the plain vector of elements
+        assert_eq!(ser, serialize(&witness.to_vec()).unwrap());
+
+        let des: Witness = deserialize(&ser).unwrap();
+        assert_eq!(des, witness);
+        assert_eq!(des.last(), Some(&[0xcd; 0x10000][..]));
+
+        let empty: Witness = deserialize(&[0]).unwrap();
+        assert!(empty.is_empty());
+        assert!(deserialize::<Witness>(&[2, 1, 0xff]).is_err());
+    }
+}
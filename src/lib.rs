@@ -41,6 +41,7 @@
 #![deny(unused_mut)]
 #![deny(missing_docs)]
 
+extern crate bech32;
 extern crate bitcoin_bech32;
 extern crate byteorder;
 extern crate crypto;
@@ -142,3 +142,57 @@ macro_rules! user_enum {
     );
 }
 
+
+/// Implements `ConsensusEncodable` and `ConsensusDecodable` for a struct by
+/// (de)serializing the listed fields in order. Every field must be listed,
+/// and the order given is the order on the wire.
+///
+/// ```ignore
+/// struct Entry { value: u64, script: Script }
+/// consensus_encodable_struct!(Entry, value, script);
+/// ```
+#[macro_export]
+macro_rules! consensus_encodable_struct {
+    ($thing:ident, $($field:ident),+) => (
+        impl<S: $crate::network::serialize::SimpleEncoder> $crate::network::encodable::ConsensusEncodable<S> for $thing {
+            #[inline]
+            fn consensus_encode(&self, s: &mut S) -> Result<(), S::Error> {
+                $( try!($crate::network::encodable::ConsensusEncodable::consensus_encode(&self.$field, s)); )+
+                Ok(())
+            }
+        }
+
+        impl<D: $crate::network::serialize::SimpleDecoder> $crate::network::encodable::ConsensusDecodable<D> for $thing {
+            #[inline]
+            fn consensus_decode(d: &mut D) -> Result<$thing, D::Error> {
+                Ok($thing {
+                    $( $field: try!($crate::network::encodable::ConsensusDecodable::consensus_decode(d)), )+
+                })
+            }
+        }
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use serialize::hex::FromHex;
+
+    use blockdata::script::Script;
+    use network::serialize::{deserialize, serialize};
+
+    #[derive(PartialEq, Debug)]
+    struct Entry {
+        value: u64,
+        script: Script
+    }
+    consensus_encodable_struct!(Entry, value, script);
+
+    #[test]
+    fn consensus_encodable_struct_round_trip() {
+        let entry = Entry { value: 0x0102, script: hex_script!("51") };
+        let ser = serialize(&entry).unwrap();
+        assert_eq!(ser, vec![2, 1, 0, 0, 0, 0, 0, 0, 1, 0x51]);
+        assert_eq!(deserialize::<Entry>(&ser).unwrap(), entry);
+        assert!(deserialize::<Entry>(&ser[..9]).is_err());
+    }
+}
@@ -32,6 +32,26 @@ user_enum! {
     }
 }
 
+impl Network {
+    /// Whether this is a test network. Test networks share their base58
+    /// address and WIF version bytes.
+    pub fn is_testnet_like(&self) -> bool {
+        match *self {
+            Network::Bitcoin => false,
+            Network::Testnet => true
+        }
+    }
+
+    /// The name of the chain as used by Bitcoin Core, e.g. in the `chain` field
+    /// of `getblockchaininfo`. `Display` gives this library's own names instead.
+    pub fn core_chain_name(&self) -> &'static str {
+        match *self {
+            Network::Bitcoin => "main",
+            Network::Testnet => "test"
+        }
+    }
+}
+
 /// Version of the protocol as appearing in network message headers
 pub const PROTOCOL_VERSION: u32    = 70001;
 /// Bitfield of services provided by this node
@@ -84,5 +104,21 @@ mod tests {
     let bad: Result<Network, _> = deserialize("fakenet".as_bytes());
     assert!(bad.is_err());
   }
-}
 
+  #[test]
+  fn names_test() {
+    fn assert_copy<T: Copy>() {}
+    assert_copy::<Network>();
+
+    assert_eq!(Network::Bitcoin.to_string(), "bitcoin");
+    assert_eq!(format!("{:?}", Network::Testnet), "testnet");
+    assert_eq!(Network::Bitcoin.core_chain_name(), "main");
+    assert_eq!(Network::Testnet.core_chain_name(), "test");
+  }
+
+  #[test]
+  fn testnet_like_test() {
+    assert!(!Network::Bitcoin.is_testnet_like());
+    assert!(Network::Testnet.is_testnet_like());
+  }
+}
@@ -209,6 +209,10 @@ impl<D: SimpleDecoder, T: ConsensusDecodable<D>> ConsensusDecodable<D> for Vec<T
         if byte_size > MAX_VEC_SIZE {
             return Err(d.error(format!("tried to allocate vec of size {} (max {})", byte_size, MAX_VEC_SIZE)));
         }
+        let max_items = d.limits().max_vec_items;
+        if len > max_items as u64 {
+            return Err(d.error(format!("tried to decode vec of {} elements (max {})", len, max_items)));
+        }
         let mut ret = Vec::with_capacity(len as usize);
         for _ in 0..len { ret.push(try!(ConsensusDecodable::consensus_decode(d))); }
         Ok(ret)
@@ -228,6 +232,10 @@ impl<D: SimpleDecoder, T: ConsensusDecodable<D>> ConsensusDecodable<D> for Box<[
         if len > MAX_VEC_SIZE {
             return Err(d.error(format!("tried to allocate vec of size {} (max {})", len, MAX_VEC_SIZE)));
         }
+        let max_items = d.limits().max_vec_items;
+        if len > max_items {
+            return Err(d.error(format!("tried to decode vec of {} elements (max {})", len, max_items)));
+        }
         let mut ret = Vec::with_capacity(len);
         for _ in 0..len { ret.push(try!(ConsensusDecodable::consensus_decode(d))); }
         Ok(ret.into_boxed_slice())
@@ -520,6 +528,19 @@ mod tests {
         assert_eq!(deserialize(&[6u8, 0x41, 0x6e, 0x64, 0x72, 0x65, 0x77]).ok(), Some("Andrew".to_string()));
     }
 
+    #[test]
+    fn strbuf_round_trip_test() {
+        for s in &["", "/Satoshi:0.16.0/", "\u{e9}t\u{e9} \u{20bf}"] {
+            let ser = serialize(&s.to_string()).unwrap();
+            // the length prefix counts bytes, not characters
+            assert_eq!(ser[0] as usize, s.len());
+            assert_eq!(deserialize::<String>(&ser).unwrap(), *s);
+        }
+        assert_eq!(serialize(&String::new()).ok(), Some(vec![0u8]));
+        // invalid UTF-8 is rejected
+        assert!(deserialize::<String>(&[2u8, 0xc3, 0x28]).is_err());
+    }
+
     #[test]
     fn deserialize_checkeddata_test() {
         let cd: Result<CheckedData, _> = deserialize(&[5u8, 0, 0, 0, 162, 107, 175, 90, 1, 2, 3, 4, 5]);
@@ -63,6 +63,7 @@ impl<D: SimpleDecoder> ConsensusDecodable<D> for CommandString {
 }
 
 /// A Network message
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub struct RawNetworkMessage {
     /// Magic bytes to identify the network these messages are meant for
     pub magic: u32,
@@ -211,6 +212,7 @@ impl<D: SimpleDecoder<Error=util::Error>> ConsensusDecodable<D> for RawNetworkMe
 mod test {
     use super::{RawNetworkMessage, NetworkMessage, CommandString};
 
+    use network::constants::{magic, Network};
     use network::serialize::{deserialize, serialize};
 
     #[test]
@@ -246,6 +248,22 @@ mod test {
                                        0x64, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]));
     }
 
+    #[test]
+    fn round_trip_verack_test() {
+        let msg = RawNetworkMessage { magic: magic(Network::Testnet), payload: NetworkMessage::Verack };
+        let mut frame = serialize(&msg).unwrap();
+        assert_eq!(frame, vec![0x0b, 0x11, 0x09, 0x07, 0x76, 0x65, 0x72, 0x61,
+                               0x63, 0x6B, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                               0x00, 0x00, 0x00, 0x00, 0x5d, 0xf6, 0xe0, 0xe2]);
+        assert_eq!(deserialize::<RawNetworkMessage>(&frame).unwrap(), msg);
+
+        let ping = RawNetworkMessage { magic: magic(Network::Bitcoin), payload: NetworkMessage::Ping(100) };
+        assert_eq!(deserialize::<RawNetworkMessage>(&serialize(&ping).unwrap()).unwrap(), ping);
+
+        // corrupt the checksum
+        frame[23] ^= 1;
+        assert!(deserialize::<RawNetworkMessage>(&frame).is_err());
+    }
 
     #[test]
     fn serialize_mempool_test() {
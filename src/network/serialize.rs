@@ -19,11 +19,13 @@
 //! It also defines (de)serialization routines for many primitives.
 //!
 
-use std::io::{Cursor, Read, Write};
+use std::io::{self, BufRead, Cursor, Read, Write};
+use std::marker::PhantomData;
+use std::mem;
 use byteorder::{LittleEndian, WriteBytesExt, ReadBytesExt};
 use serialize::hex::ToHex;
 
-use network::encodable::{ConsensusDecodable, ConsensusEncodable};
+use network::encodable::{ConsensusDecodable, ConsensusEncodable, MAX_VEC_SIZE};
 use util::hash::Sha256dHash;
 use util;
 
@@ -57,6 +59,39 @@ pub fn serialize_hex<T: ?Sized>(data: &T) -> Result<String, util::Error>
     Ok(serial.to_hex())
 }
 
+/// Compute the length of an object's serialization without allocating
+pub fn serialized_size<T: ?Sized>(data: &T) -> usize
+     where T: ConsensusEncodable<RawEncoder<SizeSink>>
+{
+    let mut encoder = RawEncoder::new(SizeSink::default());
+    // unwrap is safe as writing to a `SizeSink` cannot fail
+    data.consensus_encode(&mut encoder).unwrap();
+    encoder.into_inner().len()
+}
+
+/// A writer which discards everything written to it, only counting the bytes
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct SizeSink(usize);
+
+impl SizeSink {
+    /// The number of bytes written so far
+    pub fn len(&self) -> usize { self.0 }
+
+    /// Whether nothing has been written yet
+    pub fn is_empty(&self) -> bool { self.0 == 0 }
+}
+
+impl Write for SizeSink {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> { Ok(()) }
+}
+
 /// Deserialize an object from a vector
 pub fn deserialize<'a, T>(data: &'a [u8]) -> Result<T, util::Error>
      where T: ConsensusDecodable<RawDecoder<Cursor<&'a [u8]>>>
@@ -65,6 +100,83 @@ pub fn deserialize<'a, T>(data: &'a [u8]) -> Result<T, util::Error>
     ConsensusDecodable::consensus_decode(&mut decoder)
 }
 
+/// Deserialize an object from a vector, failing if it exceeds any of `limits`
+pub fn deserialize_with_limits<'a, T>(data: &'a [u8], limits: DecodeLimits) -> Result<T, util::Error>
+     where T: ConsensusDecodable<RawDecoder<Cursor<&'a [u8]>>>
+{
+    let mut decoder = RawDecoder::with_limits(Cursor::new(data), limits);
+    ConsensusDecodable::consensus_decode(&mut decoder)
+}
+
+/// Limits on the size of decoded data, for defense in depth when decoding
+/// untrusted input such as P2P messages. The defaults are the same fixed
+/// limits that apply when no limits are given.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct DecodeLimits {
+    /// The most elements in any one vector, e.g. the inputs of a transaction
+    pub max_vec_items: usize,
+    /// The largest script, in bytes
+    pub max_script_size: usize,
+    /// The most bytes a decoder will read in total, over everything it
+    /// decodes: a whole block or message, not each transaction in it
+    pub max_total_bytes: usize,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> DecodeLimits {
+        DecodeLimits {
+            max_vec_items: MAX_VEC_SIZE,
+            max_script_size: MAX_VEC_SIZE,
+            max_total_bytes: usize::max_value(),
+        }
+    }
+}
+
+/// An iterator decoding consecutive objects from a reader, e.g. the
+/// transactions of a block file or the messages of a P2P stream, without
+/// reading the whole stream up front. Iteration stops cleanly if the reader
+/// is at EOF between two objects. An object cut short by EOF gives an error,
+/// after which the iterator is exhausted.
+pub struct StreamDecoder<R, T> {
+    decoder: RawDecoder<R>,
+    done: bool,
+    item: PhantomData<T>
+}
+
+impl<R: BufRead, T> StreamDecoder<R, T> {
+    /// Constructor. Wrap unbuffered readers in an `io::BufReader`, which is
+    /// needed to detect EOF without consuming data.
+    pub fn new(reader: R) -> StreamDecoder<R, T> {
+        StreamDecoder {
+            decoder: RawDecoder::new(reader),
+            done: false,
+            item: PhantomData
+        }
+    }
+
+    /// Returns the underlying Reader
+    pub fn into_inner(self) -> R { self.decoder.into_inner() }
+}
+
+impl<R: BufRead, T> Iterator for StreamDecoder<R, T>
+    where T: ConsensusDecodable<RawDecoder<R>>
+{
+    type Item = Result<T, util::Error>;
+
+    fn next(&mut self) -> Option<Result<T, util::Error>> {
+        if self.done {
+            return None;
+        }
+        let ret = match self.decoder.reader.fill_buf() {
+            Ok(buf) if buf.is_empty() => { self.done = true; return None; }
+            Ok(_) => ConsensusDecodable::consensus_decode(&mut self.decoder),
+            Err(e) => Err(util::Error::Io(e))
+        };
+        self.done = ret.is_err();
+        Some(ret)
+    }
+}
+
 /// An encoder for raw binary data
 pub struct RawEncoder<W> {
     writer: W
@@ -72,7 +184,9 @@ pub struct RawEncoder<W> {
 
 /// An decoder for raw binary data
 pub struct RawDecoder<R> {
-    reader: R
+    reader: R,
+    limits: DecodeLimits,
+    bytes_read: usize
 }
 
 impl<W: Write> RawEncoder<W> {
@@ -84,9 +198,24 @@ impl<W: Write> RawEncoder<W> {
 
 impl<R: Read> RawDecoder<R> {
   /// Constructor
-  pub fn new(reader: R) -> RawDecoder<R> { RawDecoder { reader: reader } }
+  pub fn new(reader: R) -> RawDecoder<R> { RawDecoder::with_limits(reader, DecodeLimits::default()) }
+  /// Constructor for a decoder which fails once any of `limits` is exceeded
+  pub fn with_limits(reader: R, limits: DecodeLimits) -> RawDecoder<R> {
+      RawDecoder { reader: reader, limits: limits, bytes_read: 0 }
+  }
   /// Returns the underlying Reader
   pub fn into_inner(self) -> R { self.reader }
+
+  /// Counts `n` more bytes against the total size limit
+  #[inline]
+  fn consume(&mut self, n: usize) -> Result<(), util::Error> {
+      self.bytes_read += n;
+      if self.bytes_read > self.limits.max_total_bytes {
+          let max = self.limits.max_total_bytes;
+          return Err(self.error(format!("tried to decode more than {} bytes", max)));
+      }
+      Ok(())
+  }
 }
 
 /// A simple Encoder trait
@@ -144,6 +273,10 @@ pub trait SimpleDecoder {
 
     /// Signal a decoding error
     fn error(&mut self, err: String) -> Self::Error;
+
+    /// The limits on decoded sizes which decoders of vectors and scripts should
+    /// enforce
+    fn limits(&self) -> DecodeLimits { DecodeLimits::default() }
 }
 
 macro_rules! encoder_fn {
@@ -159,6 +292,7 @@ macro_rules! decoder_fn {
     ($name:ident, $val_type:ty, $readfn:ident) => {
         #[inline]
         fn $name(&mut self) -> Result<$val_type, util::Error> {
+            try!(self.consume(mem::size_of::<$val_type>()));
             self.reader.$readfn::<LittleEndian>().map_err(util::Error::ByteOrder)
         }
     }
@@ -200,14 +334,17 @@ impl<R: Read> SimpleDecoder for RawDecoder<R> {
 
     #[inline]
     fn read_u8(&mut self) -> Result<u8, util::Error> {
+        try!(self.consume(1));
         self.reader.read_u8().map_err(util::Error::ByteOrder)
     }
     #[inline]
     fn read_i8(&mut self) -> Result<i8, util::Error> {
+        try!(self.consume(1));
         self.reader.read_i8().map_err(util::Error::ByteOrder)
     }
     #[inline]
     fn read_bool(&mut self) -> Result<bool, util::Error> {
+        try!(self.consume(1));
         match self.reader.read_i8() {
             Ok(bit) => Ok(bit != 0),
             Err(e) => Err(util::Error::ByteOrder(e))
@@ -218,6 +355,9 @@ impl<R: Read> SimpleDecoder for RawDecoder<R> {
     fn error(&mut self, err: String) -> util::Error {
         util::Error::Detail(err, Box::new(util::Error::ParseFailed))
     }
+
+    #[inline]
+    fn limits(&self) -> DecodeLimits { self.limits }
 }
 
 // Aren't really any tests here.. the main functions are serialize and
@@ -18,13 +18,17 @@
 use std::str::FromStr;
 use std::string::ToString;
 
+use bech32::{self, Bech32};
 use bitcoin_bech32::{self, WitnessProgram};
+use secp256k1::Secp256k1;
 use secp256k1::key::PublicKey;
+use serde;
 
 use blockdata::script;
 use blockdata::opcodes;
 use network::constants::Network;
 use util::hash::Hash160;
+use util::key;
 use util::base58;
 use util::Error;
 
@@ -41,6 +45,81 @@ pub enum Payload {
     WitnessProgram(WitnessProgram),
 }
 
+/// The variants of `Payload`, needed to read back its binary form
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PayloadType {
+    /// pay-to-pubkey
+    Pubkey,
+    /// pay-to-pkhash
+    PubkeyHash,
+    /// P2SH
+    ScriptHash,
+    /// Segwit
+    WitnessProgram,
+}
+
+impl Payload {
+    /// The variant of this payload
+    pub fn payload_type(&self) -> PayloadType {
+        match *self {
+            Payload::Pubkey(_) => PayloadType::Pubkey,
+            Payload::PubkeyHash(_) => PayloadType::PubkeyHash,
+            Payload::ScriptHash(_) => PayloadType::ScriptHash,
+            Payload::WitnessProgram(_) => PayloadType::WitnessProgram,
+        }
+    }
+
+    /// A compact binary form of the payload without its type or network: the
+    /// compressed key, the 20-byte hash, or the witness version followed by
+    /// the witness program
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match *self {
+            Payload::Pubkey(ref pk) => pk.serialize().to_vec(),
+            Payload::PubkeyHash(ref hash) | Payload::ScriptHash(ref hash) => hash[..].to_vec(),
+            Payload::WitnessProgram(ref witprog) => {
+                let mut ret = Vec::with_capacity(1 + witprog.program().len());
+                ret.push(witprog.version());
+                ret.extend_from_slice(witprog.program());
+                ret
+            }
+        }
+    }
+
+    /// Reads back the output of `to_bytes`. The network is needed as witness
+    /// programs carry one; as when parsing addresses, only version 0 witness
+    /// programs are supported.
+    pub fn from_bytes_typed(ty: PayloadType, network: Network, data: &[u8]) -> Result<Payload, Error> {
+        match ty {
+            PayloadType::Pubkey => {
+                let secp = Secp256k1::without_caps();
+                Ok(Payload::Pubkey(try!(PublicKey::from_slice(&secp, data))))
+            },
+            PayloadType::PubkeyHash | PayloadType::ScriptHash => {
+                if data.len() != 20 {
+                    return Err(Error::ParseFailed);
+                }
+                let hash = Hash160::from(data);
+                Ok(if ty == PayloadType::PubkeyHash {
+                    Payload::PubkeyHash(hash)
+                } else {
+                    Payload::ScriptHash(hash)
+                })
+            },
+            PayloadType::WitnessProgram => {
+                let (&version, program) = match data.split_first() {
+                    Some(split) => split,
+                    None => return Err(Error::ParseFailed)
+                };
+                if version != 0 {
+                    return Err(Error::UnsupportedWitnessVersion(version));
+                }
+                let witprog = try!(WitnessProgram::new(version, program.to_vec(), Address::bech_network(network)));
+                Ok(Payload::WitnessProgram(witprog))
+            },
+        }
+    }
+}
+
 #[derive(Clone, PartialEq)]
 /// A Bitcoin address
 pub struct Address {
@@ -50,6 +129,59 @@ pub struct Address {
     pub network: Network
 }
 
+/// The hashes of a script committed to by P2SH and P2WSH addresses, computed
+/// once so the same script can be wrapped several ways without rehashing it
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct ScriptHashes {
+    /// The HASH160 of the script, as used by P2SH
+    pub script_hash: Hash160,
+    /// The SHA256 of the script, as used by P2WSH
+    pub wscript_hash: [u8; 32],
+}
+
+impl ScriptHashes {
+    /// Computes the hashes of `script`
+    pub fn new(script: &script::Script) -> ScriptHashes {
+        ScriptHashes {
+            script_hash: Hash160::from_data(&script[..]),
+            wscript_hash: ScriptHashes::wscript_hash(script),
+        }
+    }
+
+    fn wscript_hash(script: &script::Script) -> [u8; 32] {
+        use crypto::sha2::Sha256;
+        use crypto::digest::Digest;
+
+        let mut digest = Sha256::new();
+        digest.input(&script[..]);
+        let mut d = [0u8; 32];
+        digest.result(&mut d);
+        d
+    }
+}
+
+/// The single-key address types, see `bip32::ExtendedPubKey::derive_addresses`
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum AddressType {
+    /// pay-to-pkhash
+    P2pkh,
+    /// pay-to-witness-pkhash nested in P2SH
+    P2shP2wpkh,
+    /// native pay-to-witness-pkhash
+    P2wpkh,
+}
+
+/// The addresses a single public key can be paid to, see `key::PublicKey::addresses`
+#[derive(Clone, PartialEq, Debug)]
+pub struct AddressSet {
+    /// The pay-to-pubkey-hash address
+    pub p2pkh: Address,
+    /// The native segwit address, `None` for uncompressed keys
+    pub p2wpkh: Option<Address>,
+    /// The segwit address nested in p2sh, `None` for uncompressed keys
+    pub p2sh_p2wpkh: Option<Address>,
+}
+
 impl Address {
     /// Creates a pay to (compressed) public key hash address from a public key
     /// This is the preferred non-witness type address
@@ -110,7 +242,7 @@ impl Address {
     /// This is a segwit address type that looks familiar (as p2sh) to legacy clients
     pub fn p2shwpkh (pk: &PublicKey, network: Network) -> Address {
         let builder = script::Builder::new()
-            .push_int(0)
+            .push_witness_version(script::WitnessVersion::v0())
             .push_slice(&Hash160::from_data(&pk.serialize()[..])[..]);
         Address {
             network: network,
@@ -120,36 +252,57 @@ impl Address {
         }
     }
 
+    /// Create a pay to script address that embeds a witness pay to public key,
+    /// as `p2shwpkh`, but refusing uncompressed keys since segwit outputs paying
+    /// to them are unspendable under standardness rules
+    pub fn p2sh_p2wpkh(pk: &key::PublicKey, network: Network) -> Result<Address, Error> {
+        if !pk.compressed {
+            return Err(Error::UncompressedPubkey);
+        }
+        Ok(Address::p2shwpkh(&pk.key, network))
+    }
+
     /// Create a witness pay to script hash address
     pub fn p2wsh (script: &script::Script, network: Network) -> Address {
-        use crypto::sha2::Sha256;
-        use crypto::digest::Digest;
+        Address::p2wsh_from_wscript_hash(&ScriptHashes::wscript_hash(script), network)
+    }
 
-        let mut digest = Sha256::new();
-        digest.input(script.clone().into_vec().as_slice());
-        let mut d = [0u8; 32];
-        digest.result(&mut d);
+    /// Create a pay to script address that embeds a witness pay to script hash address
+    /// This is a segwit address type that looks familiar (as p2sh) to legacy clients
+    pub fn p2shwsh (script: &script::Script, network: Network) -> Address {
+        Address::p2shwsh_from_wscript_hash(&ScriptHashes::wscript_hash(script), network)
+    }
 
+    /// Create a P2SH address from precomputed script hashes, as `p2sh`
+    pub fn p2sh_from_hashes(hashes: &ScriptHashes, network: Network) -> Address {
+        Address {
+            network: network,
+            payload: Payload::ScriptHash(hashes.script_hash)
+        }
+    }
+
+    /// Create a P2WSH address from precomputed script hashes, as `p2wsh`
+    pub fn p2wsh_from_hashes(hashes: &ScriptHashes, network: Network) -> Address {
+        Address::p2wsh_from_wscript_hash(&hashes.wscript_hash, network)
+    }
+
+    /// Create a P2SH-wrapped P2WSH address from precomputed script hashes, as `p2shwsh`
+    pub fn p2shwsh_from_hashes(hashes: &ScriptHashes, network: Network) -> Address {
+        Address::p2shwsh_from_wscript_hash(&hashes.wscript_hash, network)
+    }
+
+    fn p2wsh_from_wscript_hash(wscript_hash: &[u8; 32], network: Network) -> Address {
         Address {
             network: network,
             payload: Payload::WitnessProgram(
                 // unwrap is safe as witness program is known to be correct as above
-                WitnessProgram::new(0, d.to_vec(), Address::bech_network(network)).unwrap()
+                WitnessProgram::new(0, wscript_hash.to_vec(), Address::bech_network(network)).unwrap()
             )
         }
     }
 
-    /// Create a pay to script address that embeds a witness pay to script hash address
-    /// This is a segwit address type that looks familiar (as p2sh) to legacy clients
-    pub fn p2shwsh (script: &script::Script, network: Network) -> Address {
-        use crypto::sha2::Sha256;
-        use crypto::digest::Digest;
-
-        let mut digest = Sha256::new();
-        digest.input(script.clone().into_vec().as_slice());
-        let mut d = [0u8; 32];
-        digest.result(&mut d);
-        let ws = script::Builder::new().push_int(0).push_slice(&d).into_script();
+    fn p2shwsh_from_wscript_hash(wscript_hash: &[u8; 32], network: Network) -> Address {
+        let ws = script::Builder::new().push_witness_version(script::WitnessVersion::v0()).push_slice(wscript_hash).into_script();
 
         Address {
             network: network,
@@ -166,6 +319,41 @@ impl Address {
         }
     }
 
+    /// Recovers the address a script pubkey pays to. Returns `None` for
+    /// scripts without an address form, such as bare multisig, `OP_RETURN`
+    /// outputs, pay-to-pubkey and witness versions other than 0.
+    pub fn from_script(script: &script::Script, network: Network) -> Option<Address> {
+        let payload = if script.is_p2pkh() {
+            Payload::PubkeyHash(Hash160::from(&script[3..23]))
+        } else if script.is_p2sh() {
+            Payload::ScriptHash(Hash160::from(&script[2..22]))
+        } else if script.is_v0_p2wpkh() || script.is_v0_p2wsh() {
+            Payload::WitnessProgram(
+                // unwrap is safe as both program lengths are valid for version 0
+                WitnessProgram::new(0, script[2..].to_vec(), Address::bech_network(network)).unwrap()
+            )
+        } else {
+            return None;
+        };
+
+        Some(Address {
+            network: network,
+            payload: payload,
+        })
+    }
+
+    /// Whether `other` pays to the same script as this address, possibly on a
+    /// different network
+    pub fn same_payload(&self, other: &Address) -> bool {
+        match (&self.payload, &other.payload) {
+            // the bech32 witness program carries a network of its own
+            (&Payload::WitnessProgram(ref a), &Payload::WitnessProgram(ref b)) => {
+                a.version() == b.version() && a.program() == b.program()
+            }
+            (a, b) => a == b,
+        }
+    }
+
     /// Generates a script pubkey spending to this address
     pub fn script_pubkey(&self) -> script::Script {
         match self.payload {
@@ -189,43 +377,50 @@ impl Address {
                     .push_opcode(opcodes::All::OP_EQUAL)
             },
             Payload::WitnessProgram(ref witprog) => {
+                // unwrap is safe as `WitnessProgram` only allows versions up to 16
+                let version = script::WitnessVersion::new(witprog.version()).unwrap();
                 script::Builder::new()
-                    .push_int(witprog.version() as i64)
+                    .push_witness_version(version)
                     .push_slice(witprog.program())
             }
         }.into_script()
     }
 }
 
-impl ToString for Address {
-    fn to_string(&self) -> String {
-        match self.payload {
+/// A string encoding of addresses
+pub trait AddressEncoding {
+    /// Encodes an address as a string
+    fn encode(&self, addr: &Address) -> String;
+
+    /// Decodes an address from a string
+    fn decode(&self, s: &str) -> Result<Address, Error>;
+}
+
+/// The standard address encoding used by `ToString` and `FromStr`: base58check
+/// for legacy addresses and BIP173 bech32 for segwit addresses
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct Bip173Encoding;
+
+impl AddressEncoding for Bip173Encoding {
+    fn encode(&self, addr: &Address) -> String {
+        match addr.payload {
             // note: serialization for pay-to-pk is defined, but is irreversible
             Payload::Pubkey(ref pk) => {
                 let hash = &Hash160::from_data(&pk.serialize_uncompressed()[..]);
                 let mut prefixed = [0; 21];
-                prefixed[0] = match self.network {
-                    Network::Bitcoin => 0,
-                    Network::Testnet => 111,
-                };
+                prefixed[0] = if addr.network.is_testnet_like() { 111 } else { 0 };
                 prefixed[1..].copy_from_slice(&hash[..]);
                 base58::check_encode_slice(&prefixed[..])
             },
             Payload::PubkeyHash(ref hash) => {
                 let mut prefixed = [0; 21];
-                prefixed[0] = match self.network {
-                    Network::Bitcoin => 0,
-                    Network::Testnet => 111,
-                };
+                prefixed[0] = if addr.network.is_testnet_like() { 111 } else { 0 };
                 prefixed[1..].copy_from_slice(&hash[..]);
                 base58::check_encode_slice(&prefixed[..])
             },
             Payload::ScriptHash(ref hash) => {
                 let mut prefixed = [0; 21];
-                prefixed[0] = match self.network {
-                    Network::Bitcoin => 5,
-                    Network::Testnet => 196,
-                };
+                prefixed[0] = if addr.network.is_testnet_like() { 196 } else { 5 };
                 prefixed[1..].copy_from_slice(&hash[..]);
                 base58::check_encode_slice(&prefixed[..])
             },
@@ -234,12 +429,8 @@ impl ToString for Address {
             },
         }
     }
-}
-
-impl FromStr for Address {
-    type Err = Error;
 
-    fn from_str(s: &str) -> Result<Address, Error> {
+    fn decode(&self, s: &str) -> Result<Address, Error> {
         // bech32 (note that upper or lowercase is allowed but NOT mixed case)
         if s.len() >= 3 &&
            (&s.as_bytes()[0..3] == b"bc1" || &s.as_bytes()[0..3] == b"tb1" ||
@@ -260,28 +451,24 @@ impl FromStr for Address {
         }
 
         // Base 58
-        let data = try!(base58::from_check(s));
-
-        if data.len() != 21 {
-            return Err(Error::Base58(base58::Error::InvalidLength(data.len())));
-        }
+        let (version, data) = try!(base58::decode_check_version(s, 21));
 
-        let (network, payload) = match data[0] {
+        let (network, payload) = match version {
             0 => (
                 Network::Bitcoin,
-                Payload::PubkeyHash(Hash160::from(&data[1..]))
+                Payload::PubkeyHash(Hash160::from(&data[..]))
             ),
             5 => (
                 Network::Bitcoin,
-                Payload::ScriptHash(Hash160::from(&data[1..]))
+                Payload::ScriptHash(Hash160::from(&data[..]))
             ),
             111 => (
                 Network::Testnet,
-                Payload::PubkeyHash(Hash160::from(&data[1..]))
+                Payload::PubkeyHash(Hash160::from(&data[..]))
             ),
             196 => (
                 Network::Testnet,
-                Payload::ScriptHash(Hash160::from(&data[1..]))
+                Payload::ScriptHash(Hash160::from(&data[..]))
             ),
             x   => return Err(Error::Base58(base58::Error::InvalidVersion(vec![x])))
         };
@@ -293,12 +480,158 @@ impl FromStr for Address {
     }
 }
 
+impl ToString for Address {
+    fn to_string(&self) -> String {
+        Bip173Encoding.encode(self)
+    }
+}
+
+/// Parses an address in the encoding of `Bip173Encoding`.
+///
+/// Regtest uses the same base58 version bytes as testnet (111 for P2PKH and
+/// 196 for P2SH), and `Network` has no regtest variant, so a base58 address
+/// meant for regtest parses as a `Testnet` address. It has the same
+/// scriptPubKey on either network.
+impl FromStr for Address {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Address, Error> {
+        Bip173Encoding.decode(s)
+    }
+}
+
+/// A segwit address with an arbitrary human-readable part, such as one for a
+/// custom signet, which cannot be an `Address` as its network is not known
+/// to this library. Only the bech32 encoding and the witness program are
+/// checked, so such an address can be read, paid to and written back out.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct UnvalidatedAddress {
+    hrp: String,
+    version: script::WitnessVersion,
+    program: Vec<u8>,
+}
+
+impl UnvalidatedAddress {
+    /// The human-readable part, in lowercase
+    pub fn hrp(&self) -> &str { &self.hrp }
+
+    /// The witness version
+    pub fn version(&self) -> u8 { self.version.to_u8() }
+
+    /// The witness program
+    pub fn program(&self) -> &[u8] { &self.program }
+
+    /// Generates a script pubkey spending to this address
+    pub fn script_pubkey(&self) -> script::Script {
+        script::Builder::new()
+            .push_witness_version(self.version)
+            .push_slice(&self.program)
+            .into_script()
+    }
+}
+
+impl ToString for UnvalidatedAddress {
+    fn to_string(&self) -> String {
+        let mut data = vec![self.version.to_u8()];
+        // unwraps are safe as the fields are private and were checked by `from_str`
+        data.extend(bech32::convert_bits(&self.program, 8, 5, true).unwrap());
+        Bech32::new(self.hrp.clone(), data).unwrap().to_string()
+    }
+}
+
+impl FromStr for UnvalidatedAddress {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<UnvalidatedAddress, Error> {
+        let bech = try!(Bech32::from_str(s).map_err(bitcoin_bech32::Error::Bech32));
+        let (hrp, data) = bech.into_parts();
+        if data.is_empty() {
+            return Err(Error::Bech32(bitcoin_bech32::Error::InvalidLength));
+        }
+        let program = try!(bech32::convert_bits(&data[1..], 5, 8, false).map_err(bitcoin_bech32::Error::Bech32));
+        let version = match script::WitnessVersion::new(data[0]) {
+            Some(version) => version,
+            None => return Err(Error::Bech32(bitcoin_bech32::Error::InvalidScriptVersion)),
+        };
+        if program.len() < 2 || program.len() > 40 {
+            return Err(Error::Bech32(bitcoin_bech32::Error::InvalidLength));
+        }
+        if version == script::WitnessVersion::v0() && program.len() != 20 && program.len() != 32 {
+            return Err(Error::Bech32(bitcoin_bech32::Error::InvalidVersionLength));
+        }
+        Ok(UnvalidatedAddress {
+            hrp: hrp.to_lowercase(),
+            version: version,
+            program: program,
+        })
+    }
+}
+
 impl ::std::fmt::Debug for Address {
     fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
         write!(f, "{}", self.to_string())
     }
 }
 
+// User-facing serialization
+impl serde::Serialize for Address {
+    fn serialize<S>(&self, s: &mut S) -> Result<(), S::Error>
+        where S: serde::Serializer,
+    {
+        s.visit_str(&self.to_string())
+    }
+}
+
+impl serde::Deserialize for Address {
+    fn deserialize<D>(d: &mut D) -> Result<Address, D::Error>
+        where D: serde::Deserializer
+    {
+        struct AddressVisitor;
+        impl serde::de::Visitor for AddressVisitor {
+            type Value = Address;
+
+            fn visit_string<E>(&mut self, v: String) -> Result<Address, E>
+                where E: serde::de::Error
+            {
+                self.visit_str(&v)
+            }
+
+            fn visit_str<E>(&mut self, s: &str) -> Result<Address, E>
+                where E: serde::de::Error
+            {
+                Address::from_str(s).map_err(|_| serde::de::Error::syntax("bad address"))
+            }
+        }
+
+        d.visit(AddressVisitor)
+    }
+}
+
+/// Returns the human-readable part of a bech32 string, i.e. everything before
+/// the last `1`, without checking the rest of the string. This is enough to
+/// tell the network of a segwit address without fully parsing it. Case is
+/// preserved. Returns `None` if there is no separator or the part before it
+/// is empty.
+pub fn bech32_hrp(s: &str) -> Option<&str> {
+    match s.rfind('1') {
+        Some(0) | None => None,
+        Some(pos) => Some(&s[..pos]),
+    }
+}
+
+/// Deserializes an address, failing unless it belongs to `network`. Use this
+/// in place of `Address::deserialize` when the address comes from a config
+/// or other input that must not silently accept a different network.
+pub fn serde_as_network<D>(d: &mut D, network: Network) -> Result<Address, D::Error>
+    where D: serde::Deserializer
+{
+    let addr: Address = try!(serde::Deserialize::deserialize(d));
+    if addr.network != network {
+        return Err(serde::de::Error::invalid_value("address is for the wrong network"));
+    }
+    Ok(addr)
+}
+
 
 #[cfg(test)]
 mod tests {
@@ -318,6 +651,78 @@ mod tests {
     macro_rules! hex_key (($secp:expr, $hex:expr) => (PublicKey::from_slice($secp, &hex!($hex)).unwrap()));
     macro_rules! hex_script (($hex:expr) => (Script::from(hex!($hex))));
 
+    #[test]
+    fn test_address_encoding() {
+        // a toy encoding of the network and script pubkey
+        struct ScriptEncoding;
+        impl AddressEncoding for ScriptEncoding {
+            fn encode(&self, addr: &Address) -> String {
+                format!("{}:{}", addr.network, addr.script_pubkey())
+            }
+
+            fn decode(&self, s: &str) -> Result<Address, Error> {
+                let mut parts = s.splitn(2, ':');
+                let network = match parts.next() {
+                    Some("bitcoin") => Bitcoin,
+                    Some("testnet") => Testnet,
+                    _ => return Err(Error::ParseFailed),
+                };
+                let script = try!(parts.next().unwrap_or("").parse().map_err(|_| Error::ParseFailed));
+                Address::from_script(&script, network).ok_or(Error::ParseFailed)
+            }
+        }
+
+        let addr = Address::from_str("bc1qvzvkjn4q3nszqxrv3nraga2r822xjty3ykvkuw").unwrap();
+        let encodings: Vec<Box<AddressEncoding>> = vec![Box::new(Bip173Encoding), Box::new(ScriptEncoding)];
+        let encoded: Vec<String> = encodings.iter().map(|e| e.encode(&addr)).collect();
+        assert_eq!(encoded[0], addr.to_string());
+        assert_eq!(encoded[1], "bitcoin:00146099694ea08ce020186c8cc7d475433a94692c91");
+        for (encoding, s) in encodings.iter().zip(encoded.iter()) {
+            assert_eq!(encoding.decode(s).unwrap(), addr);
+        }
+        assert!(ScriptEncoding.decode("regtest:00").is_err());
+    }
+
+    #[test]
+    fn test_unvalidated_address() {
+        // a custom signet address
+        let addr = UnvalidatedAddress::from_str("sb1qvzvkjn4q3nszqxrv3nraga2r822xjty3thuq8z").unwrap();
+        assert_eq!(addr.hrp(), "sb");
+        assert_eq!(addr.version(), 0);
+        assert_eq!(addr.program(), &"6099694ea08ce020186c8cc7d475433a94692c91".from_hex().unwrap()[..]);
+        assert_eq!(addr.to_string(), "sb1qvzvkjn4q3nszqxrv3nraga2r822xjty3thuq8z");
+        assert_eq!(addr.script_pubkey(), hex_script!("00146099694ea08ce020186c8cc7d475433a94692c91"));
+        // uppercase is read and written back in lowercase
+        assert_eq!(UnvalidatedAddress::from_str("SB1QVZVKJN4Q3NSZQXRV3NRAGA2R822XJTY3THUQ8Z").unwrap(), addr);
+
+        // known networks parse too, with the same program as the checked address
+        let s = "bc1qvzvkjn4q3nszqxrv3nraga2r822xjty3ykvkuw";
+        let addr = UnvalidatedAddress::from_str(s).unwrap();
+        assert_eq!(addr.to_string(), s);
+        assert_eq!(addr.script_pubkey(), Address::from_str(s).unwrap().script_pubkey());
+
+        // bad checksum, bad v0 program length
+        assert!(UnvalidatedAddress::from_str("sb1qvzvkjn4q3nszqxrv3nraga2r822xjty3thuq8y").is_err());
+        assert!(UnvalidatedAddress::from_str("BC1QR508D6QEJXTDG4Y5R3ZARVARYV98GJ9P").is_err());
+        assert!(UnvalidatedAddress::from_str("1QJVDzdqb1VpbDK7uDeyVXy9mR27CJiyhY").is_err());
+    }
+
+    #[test]
+    fn test_same_payload() {
+        let hash = Hash160::from(&"162c5ea71c0b23f5b9022ef047c4a86470a5b070".from_hex().unwrap()[..]);
+        let main = Address { network: Bitcoin, payload: Payload::PubkeyHash(hash) };
+        let test = Address { network: Testnet, payload: Payload::PubkeyHash(hash) };
+        assert!(main.same_payload(&test));
+        assert!(main != test);
+        assert!(!main.same_payload(&Address { network: Bitcoin, payload: Payload::ScriptHash(hash) }));
+
+        let script = hex_script!("0014751e76e8199196d454941c45d1b3a323f1433bd6");
+        let main = Address::from_script(&script, Bitcoin).unwrap();
+        let test = Address::from_script(&script, Testnet).unwrap();
+        assert!(main.same_payload(&test));
+        assert!(main != test);
+    }
+
     #[test]
     fn test_p2pkh_address_58() {
         let addr = Address {
@@ -332,6 +737,20 @@ mod tests {
         assert_eq!(Address::from_str("132F25rTsvBdp9JzLLBHP5mvGY66i1xdiM").unwrap(), addr);
     }
 
+    #[test]
+    fn test_regtest_base58() {
+        // `bitcoin-cli -regtest getnewaddress "" legacy` gives testnet-versioned strings
+        let secp = Secp256k1::without_caps();
+        let key = hex_key!(&secp, "03df154ebfcf29d29cc10d5c2565018bce2d9edbab267c31d2caf44a63056cf99f");
+        let regtest = base58::check_encode_slice(&[&[111][..], &Hash160::from_data(&key.serialize()[..])[..]].concat());
+        assert_eq!(regtest, "mqkhEMH6NCeYjFybv7pvFC22MFeaNT9AQC");
+
+        let addr = Address::from_str(&regtest).unwrap();
+        assert_eq!(addr.network, Testnet);
+        assert_eq!(addr, Address::p2pkh(&key, Testnet));
+        assert_eq!(addr.to_string(), regtest);
+    }
+
     #[test]
     fn test_p2pkh_from_key() {
         let secp = Secp256k1::without_caps();
@@ -377,6 +796,28 @@ mod tests {
         assert_eq!(Address::from_str("2N3zXjbwdTcPsJiy8sUK9FhWJhqQCxA8Jjr").unwrap(), addr);
     }
 
+    #[test]
+    fn test_base58_length() {
+        use std::iter;
+
+        // Longest possible p2pkh address
+        let addr = Address::from_str("1QJVDzdqb1VpbDK7uDeyVXy9mR27CJiyhY").unwrap();
+        assert_eq!(addr.to_string().len(), 34);
+
+        // Long strings fail on their first non-base58 character
+        match Address::from_str(&iter::repeat('0').take(100).collect::<String>()) {
+            Err(Error::Base58(base58::Error::BadByte(b'0'))) => {},
+            x => panic!("unexpected result {:?}", x),
+        }
+
+        // Oversized base58 payloads report the length that was actually decoded
+        let long = base58::check_encode_slice(&[0; 40]);
+        match Address::from_str(&long) {
+            Err(Error::Base58(base58::Error::InvalidLength(40))) => {},
+            x => panic!("unexpected result {:?}", x),
+        }
+    }
+
     #[test]
     fn test_p2wpkh () {
         // stolen from Bitcoin transaction: b3c8c2b6cfc335abbcb2c7823a8453f55d64b2b5125a9a61e8737230cdb8ce20
@@ -387,6 +828,130 @@ mod tests {
     }
 
 
+    #[test]
+    fn test_p2shwpkh() {
+        // BIP49 test vector
+        let secp = Secp256k1::without_caps();
+        let pubkey = hex_key!(&secp, "03a1af804ac108a8a51782198c2d034b28bf90c8803f5a53f76276fa69a4eae77f");
+        let addr = Address::p2shwpkh(&pubkey, Testnet);
+        assert_eq!(&addr.to_string(), "2Mww8dCYPUpKHofjgcXcBCEGmniw9CoaiD2");
+        // the redeem script is exactly the p2wpkh script pubkey
+        assert_eq!(addr, Address::p2sh(&Address::p2wpkh(&pubkey, Testnet).script_pubkey(), Testnet));
+
+        let pk = key::PublicKey { compressed: true, key: pubkey };
+        assert_eq!(Address::p2sh_p2wpkh(&pk, Testnet).unwrap(), addr);
+        let pk = key::PublicKey { compressed: false, key: pubkey };
+        match Address::p2sh_p2wpkh(&pk, Testnet) {
+            Err(Error::UncompressedPubkey) => {},
+            x => panic!("unexpected result {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_error_cause() {
+        use std::error::Error as StdError;
+
+        // bad checksum
+        let err = Address::from_str("1QJVDzdqb1VpbDK7uDeyVXy9mR27CJiyhZ").unwrap_err();
+        match err {
+            Error::Base58(base58::Error::BadChecksum(_, _)) => {},
+            ref x => panic!("unexpected error {:?}", x),
+        }
+        assert_eq!(err.cause().unwrap().description(), "invalid b58ck checksum");
+
+        let err = Address::from_str("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t5").unwrap_err();
+        assert!(err.cause().is_some());
+    }
+
+    #[test]
+    fn test_payload_bytes() {
+        let secp = Secp256k1::without_caps();
+        let pk = PublicKey::from_slice(&secp, &"026d5d4cfef5f3d97d2263941b4d8e7aaa82910bf8e6f7c6cf1d8f0d755b9d2d1a".from_hex().unwrap()).unwrap();
+        let script = hex_script!("552103a765fc35b3f210b95223846b36ef62a4e53e34e2925270c2c7906b92c9f718eb2103c327511374246759ec8d0b89fa6c6b23b33e11f92c5bc155409d86de0c79180121038cae7406af1f12f4786d820a1466eec7bc5785a1b5e4a387eca6d797753ef6db2103252bfb9dcaab0cd00353f2ac328954d791270203d66c2be8b430f115f451b8a12103e79412d42372c55dd336f2eb6eb639ef9d74a22041ba79382c74da2338fe58ad21035049459a4ebc00e876a9eef02e72a3e70202d3d1f591fc0dd542f93f642021f82102016f682920d9723c61b27f562eb530c926c00106004798b6471e8c52c60ee02057ae");
+        let addresses = vec![
+            Address::p2pk(&pk, Bitcoin),
+            Address::p2pkh(&pk, Bitcoin),
+            Address::p2sh(&script, Bitcoin),
+            Address::p2wpkh(&pk, Testnet),
+            Address::p2wsh(&script, Bitcoin),
+        ];
+        let lengths = [33, 20, 20, 21, 33];
+        for (addr, &len) in addresses.iter().zip(lengths.iter()) {
+            let bytes = addr.payload.to_bytes();
+            assert_eq!(bytes.len(), len);
+            let ty = addr.payload.payload_type();
+            assert_eq!(Payload::from_bytes_typed(ty, addr.network, &bytes).unwrap(), addr.payload);
+        }
+
+        assert!(Payload::from_bytes_typed(PayloadType::PubkeyHash, Bitcoin, &[0; 19]).is_err());
+        assert!(Payload::from_bytes_typed(PayloadType::ScriptHash, Bitcoin, &[0; 21]).is_err());
+        assert!(Payload::from_bytes_typed(PayloadType::Pubkey, Bitcoin, &[0; 33]).is_err());
+        assert!(Payload::from_bytes_typed(PayloadType::WitnessProgram, Bitcoin, &[]).is_err());
+        assert!(Payload::from_bytes_typed(PayloadType::WitnessProgram, Bitcoin, &[0; 10]).is_err());
+        match Payload::from_bytes_typed(PayloadType::WitnessProgram, Bitcoin, &[1; 33]) {
+            Err(Error::UnsupportedWitnessVersion(1)) => {},
+            x => panic!("unexpected {:?}", x)
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "randtest")]
+    fn test_random_round_trip() {
+        use rand::{Rng, SeedableRng, XorShiftRng};
+
+        let secp = Secp256k1::new();
+        // fixed seed so that failures can be reproduced
+        let mut rng = XorShiftRng::from_seed([0x243f6a88, 0x85a308d3, 0x13198a2e, 0x03707344]);
+        for _ in 0..500 {
+            let network = if rng.gen() { Bitcoin } else { Testnet };
+            let (_, pk) = secp.generate_keypair(&mut rng).unwrap();
+            let program_len = if rng.gen() { 20 } else { 32 };
+            let program: Vec<u8> = rng.gen_iter().take(program_len).collect();
+            let addresses = [
+                Address::p2pkh(&pk, network),
+                Address::p2upkh(&pk, network),
+                Address::p2wpkh(&pk, network),
+                Address::p2shwpkh(&pk, network),
+                Address { network: network, payload: Payload::PubkeyHash(rng.gen()) },
+                Address { network: network, payload: Payload::ScriptHash(rng.gen()) },
+                Address {
+                    network: network,
+                    payload: Payload::WitnessProgram(WitnessProgram::new(0, program, Address::bech_network(network)).unwrap())
+                },
+            ];
+            for addr in &addresses {
+                let s = addr.to_string();
+                assert_eq!(Address::from_str(&s).unwrap(), *addr, "{}", s);
+                assert_eq!(Address::from_script(&addr.script_pubkey(), network).as_ref(), Some(addr));
+            }
+
+            // Only version 0 witness programs can be parsed back
+            let version = rng.gen_range(1, 17);
+            let program_len = rng.gen_range(2, 41);
+            let program: Vec<u8> = rng.gen_iter().take(program_len).collect();
+            let witprog = WitnessProgram::new(version, program, Address::bech_network(network)).unwrap();
+            match Address::from_str(&witprog.to_address()) {
+                Err(Error::UnsupportedWitnessVersion(v)) => assert_eq!(v, version),
+                x => panic!("unexpected {:?}", x)
+            }
+        }
+    }
+
+    #[test]
+    fn test_from_script() {
+        for s in &["1QJVDzdqb1VpbDK7uDeyVXy9mR27CJiyhY", "33iFwdLuRpW1uK1RTRqsoi8rR4NpDzk66k",
+                   "bc1qvzvkjn4q3nszqxrv3nraga2r822xjty3ykvkuw",
+                   "bc1qwqdg6squsna38e46795at95yu9atm8azzmyvckulcc7kytlcckxswvvzej"] {
+            let addr = Address::from_str(s).unwrap();
+            assert_eq!(Address::from_script(&addr.script_pubkey(), Bitcoin), Some(addr));
+        }
+        let addr = Address::from_str("mqkhEMH6NCeYjFybv7pvFC22MFeaNT9AQC").unwrap();
+        assert_eq!(Address::from_script(&addr.script_pubkey(), Testnet), Some(addr));
+
+        assert_eq!(Address::from_script(&hex_script!("6a0401020304"), Bitcoin), None);
+        assert_eq!(Address::from_script(&hex_script!(""), Bitcoin), None);
+    }
+
     #[test]
     fn test_p2wsh () {
         // stolen from Bitcoin transaction 5df912fda4becb1c29e928bec8d64d93e9ba8efa9b5b405bd683c86fd2c65667
@@ -396,6 +961,19 @@ mod tests {
     }
 
 
+    #[test]
+    fn test_script_hashes() {
+        let script = hex_script!("52210375e00eb72e29da82b89367947f29ef34afb75e8654f6ea368e0acdfd92976b7c2103a1b26313f430c4b15bb1fdce663207659d8cac749a0e53d70eff01874496feff2103c96d495bfdd5ba4145e3e046fee45e84a8a48ad05bd8dbb395c011a32cf9f88053ae");
+        let hashes = ScriptHashes::new(&script);
+        for &network in &[Bitcoin, Testnet] {
+            assert_eq!(Address::p2sh_from_hashes(&hashes, network), Address::p2sh(&script, network));
+            assert_eq!(Address::p2wsh_from_hashes(&hashes, network), Address::p2wsh(&script, network));
+            assert_eq!(Address::p2shwsh_from_hashes(&hashes, network), Address::p2shwsh(&script, network));
+        }
+        assert_eq!(&Address::p2wsh_from_hashes(&hashes, Bitcoin).to_string(),
+                   "bc1qwqdg6squsna38e46795at95yu9atm8azzmyvckulcc7kytlcckxswvvzej");
+    }
+
     #[test]
     fn test_bip173_vectors() {
         let addrstr = "BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4";
@@ -447,5 +1025,98 @@ mod tests {
         let addrstr = "bc1gmk9yu"; // empty data section
         assert!(Address::from_str(addrstr).is_err());
     }
-}
 
+    /// Valid addresses as (string, network, script type, scriptPubKey)
+    ///
+    /// `Address` does not support witness versions above 0 yet, so the BIP350
+    /// vectors cannot be listed here until it does.
+    const ADDRESS_VECTORS: &'static [(&'static str, Network, &'static str, &'static str)] = &[
+        ("132F25rTsvBdp9JzLLBHP5mvGY66i1xdiM", Bitcoin, "p2pkh",
+         "76a914162c5ea71c0b23f5b9022ef047c4a86470a5b07088ac"),
+        ("1QJVDzdqb1VpbDK7uDeyVXy9mR27CJiyhY", Bitcoin, "p2pkh",
+         "76a914ff99864ce1a887e00c9c8615210d6267edd7d7a588ac"),
+        ("mqkhEMH6NCeYjFybv7pvFC22MFeaNT9AQC", Testnet, "p2pkh",
+         "76a914704a81b27f00336d6b150958b7741c5807ba954088ac"),
+        ("33iFwdLuRpW1uK1RTRqsoi8rR4NpDzk66k", Bitcoin, "p2sh",
+         "a914162c5ea71c0b23f5b9022ef047c4a86470a5b07087"),
+        ("2N3zXjbwdTcPsJiy8sUK9FhWJhqQCxA8Jjr", Testnet, "p2sh",
+         "a91475e1f56366999fa7060d943a45ba42e065b0979587"),
+        ("2Mww8dCYPUpKHofjgcXcBCEGmniw9CoaiD2", Testnet, "p2sh",
+         "a914336caa13e08b96080a32b5d818d59b4ab3b3674287"),
+        // BIP173
+        ("BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4", Bitcoin, "p2wpkh",
+         "0014751e76e8199196d454941c45d1b3a323f1433bd6"),
+        ("tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7", Testnet, "p2wsh",
+         "00201863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262"),
+        ("tb1qqqqqp399et2xygdj5xreqhjjvcmzhxw4aywxecjdzew6hylgvsesrxh6hy", Testnet, "p2wsh",
+         "0020000000c4a5cad46221b2a187905e5266362b99d5e91c6ce24d165dab93e86433"),
+        ("bc1qvzvkjn4q3nszqxrv3nraga2r822xjty3ykvkuw", Bitcoin, "p2wpkh",
+         "00146099694ea08ce020186c8cc7d475433a94692c91"),
+        ("bc1qwqdg6squsna38e46795at95yu9atm8azzmyvckulcc7kytlcckxswvvzej", Bitcoin, "p2wsh",
+         "0020701a8d401c84fb13e6baf169d59684e17abd9fa216c8cc5b9fc63d622ff8c58d"),
+    ];
+
+    #[test]
+    fn test_address_vectors() {
+        for &(addrstr, network, script_type, spk) in ADDRESS_VECTORS {
+            let addr = Address::from_str(addrstr).unwrap();
+            let spk = hex_script!(spk);
+            assert_eq!(addr.network, network, "{}", addrstr);
+            assert_eq!(addr.script_pubkey(), spk, "{}", addrstr);
+            // bech32 addresses are always written out in lowercase
+            let expected = match addr.payload {
+                Payload::WitnessProgram(_) => addrstr.to_lowercase(),
+                _ => addrstr.to_owned(),
+            };
+            assert_eq!(addr.to_string(), expected);
+            assert_eq!(Address::from_script(&spk, network), Some(addr), "{}", addrstr);
+
+            let is_type = match script_type {
+                "p2pkh" => spk.is_p2pkh(),
+                "p2sh" => spk.is_p2sh(),
+                "p2wpkh" => spk.is_v0_p2wpkh(),
+                "p2wsh" => spk.is_v0_p2wsh(),
+                x => panic!("unknown script type {}", x),
+            };
+            assert!(is_type, "{} is not {}", addrstr, script_type);
+        }
+    }
+
+    #[test]
+    fn test_bech32_hrp() {
+        assert_eq!(bech32_hrp("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4"), Some("bc"));
+        assert_eq!(bech32_hrp("BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4"), Some("BC"));
+        assert_eq!(bech32_hrp("tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7"), Some("tb"));
+        assert_eq!(bech32_hrp("bcrt1qxyz"), Some("bcrt"));
+        assert_eq!(bech32_hrp("sb1qvzvkjn4q3nszqxrv3nraga2r822xjty3thuq8z"), Some("sb"));
+        // the separator is the last 1, the HRP may contain others
+        assert_eq!(bech32_hrp("a1b1qqqqqq"), Some("a1b"));
+
+        // nothing else is checked, so base58 strings containing a 1 give an HRP
+        assert_eq!(bech32_hrp("33iFwdLuRpW1uK1RTRqsoi8rR4NpDzk66k"), Some("33iFwdLuRpW1uK"));
+        assert_eq!(bech32_hrp("mqkhEMH6NCeYjFybv7pvFC22MFeaNT9AQC"), None);
+        assert_eq!(bech32_hrp("1qqqqqq"), None);
+        assert_eq!(bech32_hrp(""), None);
+    }
+
+    #[test]
+    fn test_serde() {
+        use strason::Json;
+        use strason::object::Deserializer;
+
+        let addr = Address::from_str("1QJVDzdqb1VpbDK7uDeyVXy9mR27CJiyhY").unwrap();
+        let json = Json::from_serialize(&addr).unwrap();
+        assert_eq!(json.string(), Some("1QJVDzdqb1VpbDK7uDeyVXy9mR27CJiyhY"));
+        let des: Address = json.into_deserialize().unwrap();
+        assert_eq!(des, addr);
+
+        let json = Json::from_serialize(&addr).unwrap();
+        let des = serde_as_network(&mut Deserializer::new(json), Bitcoin).unwrap();
+        assert_eq!(des, addr);
+
+        let testnet = Json::from_str("\"2N3zXjbwdTcPsJiy8sUK9FhWJhqQCxA8Jjr\"").unwrap();
+        assert!(serde_as_network(&mut Deserializer::new(testnet), Bitcoin).is_err());
+        let testnet = Json::from_str("\"2N3zXjbwdTcPsJiy8sUK9FhWJhqQCxA8Jjr\"").unwrap();
+        assert!(serde_as_network(&mut Deserializer::new(testnet), Testnet).is_ok());
+    }
+}
@@ -32,6 +32,10 @@ pub enum Error {
     InvalidVersion(Vec<u8>),
     /// Checked data was less than 4 bytes
     TooShort(usize),
+    /// Extended key at depth 0 had a nonzero parent fingerprint or child number
+    InvalidExtendedKeyStructure,
+    /// WIF private key had a compression flag other than 0x01
+    InvalidCompressionFlag(u8),
     /// Any other error
     Other(String)
 }
@@ -44,6 +48,8 @@ impl fmt::Display for Error {
             Error::InvalidLength(ell) => write!(f, "length {} invalid for this base58 type", ell),
             Error::InvalidVersion(ref v) => write!(f, "version {:?} invalid for this base58 type", v),
             Error::TooShort(_) => write!(f, "base58ck data not even long enough for a checksum"),
+            Error::InvalidExtendedKeyStructure => f.write_str("depth 0 key with nonzero parent fingerprint or child number"),
+            Error::InvalidCompressionFlag(flag) => write!(f, "WIF compression flag 0x{:x} is not 0x01", flag),
             Error::Other(ref s) => f.write_str(s)
         }
     }
@@ -58,6 +64,8 @@ impl error::Error for Error {
             Error::InvalidLength(_) => "invalid length for b58 type",
             Error::InvalidVersion(_) => "invalid version for b58 type",
             Error::TooShort(_) => "b58ck data less than 4 bytes",
+            Error::InvalidExtendedKeyStructure => "invalid master extended key structure",
+            Error::InvalidCompressionFlag(_) => "invalid WIF compression flag",
             Error::Other(_) => "unknown b58 error"
         }
     }
@@ -84,14 +92,20 @@ static BASE58_DIGITS: [Option<u8>; 128] = [
     Some(55), Some(56), Some(57), None,     None,     None,     None,     None,     // 120-127
 ];
 
-/// Decode base58-encoded string into a byte vector
+/// Decode a plain base58-encoded string into a byte vector. No checksum is
+/// expected or verified; see `from_check` for base58check.
+pub fn decode(data: &str) -> Result<Vec<u8>, Error> {
+    from(data)
+}
+
+/// Decode base58-encoded string into a byte vector, without a checksum
 pub fn from(data: &str) -> Result<Vec<u8>, Error> {
     // 11/15 is just over log_256(58)
     let mut scratch = vec![0u8; 1 + data.len() * 11 / 15];
     // Build in base 256
     for d58 in data.bytes() {
         // Compute "X = X * 58 + next_digit" in base 256
-        if d58 as usize > BASE58_DIGITS.len() {
+        if d58 as usize >= BASE58_DIGITS.len() {
             return Err(Error::BadByte(d58));
         }
         let mut carry = match BASE58_DIGITS[d58 as usize] {
@@ -132,6 +146,18 @@ pub fn from_check(data: &str) -> Result<Vec<u8>, Error> {
     Ok(ret)
 }
 
+/// Decode a base58check-encoded string which is expected to consist of a
+/// single version byte followed by a payload, returning the two separately.
+/// `expected_len` is the decoded length including the version byte.
+pub fn decode_check_version(data: &str, expected_len: usize) -> Result<(u8, Vec<u8>), Error> {
+    let mut ret = try!(from_check(data));
+    if ret.len() != expected_len || ret.is_empty() {
+        return Err(Error::InvalidLength(ret.len()));
+    }
+    let payload = ret.split_off(1);
+    Ok((ret[0], payload))
+}
+
 fn encode_iter<I>(data: I) -> String
 where
     I: Iterator<Item = u8> + Clone,
@@ -174,7 +200,8 @@ where
     String::from_utf8(ret).unwrap()
 }
 
-/// Directly encode a slice as base58
+/// Directly encode a slice as plain base58, without appending a checksum;
+/// see `check_encode_slice` for base58check
 pub fn encode_slice(data: &[u8]) -> String {
     encode_iter(data.iter().cloned())
 }
@@ -230,6 +257,37 @@ mod tests {
                    Some("00f8917303bfa8ef24f292e8fa1419b20460ba064d".from_hex().unwrap()))
     }
 
+    #[test]
+    fn test_base58_unchecked_round_trip() {
+        let data = "00f8917303bfa8ef24f292e8fa1419b20460ba064d".from_hex().unwrap();
+        let encoded = encode_slice(&data);
+        assert_eq!(encoded, "14TrSStH2tVv3RgkLnUpJwMAmGBmv");
+        assert_eq!(decode(&encoded).unwrap(), data);
+        // a base58check string decodes to the payload followed by its checksum
+        let checked = decode("1PfJpZsjreyVrqeoAfabrRwwjQyoSQMmHH").unwrap();
+        assert_eq!(&checked[..21], &data[..]);
+        assert_eq!(checked.len(), 25);
+
+        assert_eq!(decode("1l"), Err(Error::BadByte(b'l')));
+        assert_eq!(decode("1\u{80}"), Err(Error::BadByte(0xc2)));
+        assert_eq!(decode("").unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn test_base58_decode_check_version() {
+        let (version, payload) = decode_check_version("1PfJpZsjreyVrqeoAfabrRwwjQyoSQMmHH", 21).unwrap();
+        assert_eq!(version, 0);
+        assert_eq!(payload, "f8917303bfa8ef24f292e8fa1419b20460ba064d".from_hex().unwrap());
+
+        // Wrong length is reported with the actual decoded length
+        assert_eq!(decode_check_version("1PfJpZsjreyVrqeoAfabrRwwjQyoSQMmHH", 34),
+                   Err(Error::InvalidLength(21)));
+        // Truncated strings fail, either on the checksum or the length
+        assert!(decode_check_version("1PfJpZsjreyVrqeoAfabrRwwjQyoSQ", 21).is_err());
+        let short = check_encode_slice(&"00f8917303bfa8ef24f292e8fa1419b20460ba06".from_hex().unwrap());
+        assert_eq!(decode_check_version(&short, 21), Err(Error::InvalidLength(20)));
+    }
+
     #[test]
     fn test_base58_roundtrip() {
         let s = "xprv9wTYmMFdV23N2TdNG573QoEsfRrWKQgWeibmLntzniatZvR9BmLnvSxqu53Kw1UmYPxLgboyZQaXwTCg8MSY3H2EU4pWcQDnRnrVA1xe8fs";
@@ -0,0 +1,399 @@
+// Rust Bitcoin Library
+// Written in 2018 by
+//     The Rust Bitcoin developers
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # BIP158 Compact Block Filters
+//!
+//! Golomb-coded sets of the scripts a block touches, which light clients
+//! can download and query instead of the full block, as defined by BIP158.
+//! Only the basic filter type is supported.
+//!
+
+use std::collections::HashSet;
+use std::hash::Hasher;
+#[allow(deprecated)]
+use std::hash::SipHasher;
+use std::io::Cursor;
+use std::{error, fmt};
+
+use blockdata::block::Block;
+use blockdata::script::Script;
+use blockdata::transaction::TxOutRef;
+use network::encodable::{ConsensusDecodable, VarInt};
+use network::serialize::{serialize, BitcoinHash, RawDecoder};
+use util::hash::Sha256dHash;
+
+/// Golomb-Rice parameter of the basic filter
+pub const P: u8 = 19;
+/// Inverse false positive rate of the basic filter
+pub const M: u64 = 784931;
+
+/// A BIP158 error
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// The script spent by an input of the block could not be found
+    UtxoMissing(TxOutRef),
+    /// The filter content ended before all its elements were read, or its
+    /// element count was too large
+    MalformedFilter,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::UtxoMissing(ref outref) => write!(f, "unresolved utxo {}", outref),
+            Error::MalformedFilter => f.write_str(error::Error::description(self)),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> { None }
+
+    fn description(&self) -> &str {
+        match *self {
+            Error::UtxoMissing(_) => "unresolved utxo",
+            Error::MalformedFilter => "malformed filter",
+        }
+    }
+}
+
+/// A basic block filter
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BlockFilter {
+    /// The serialized filter: the element count followed by the Golomb-Rice
+    /// coded differences of the sorted hashed elements
+    pub content: Vec<u8>,
+}
+
+impl BlockFilter {
+    /// Wraps serialized filter content, e.g. as received from a peer
+    pub fn new(content: &[u8]) -> BlockFilter {
+        BlockFilter { content: content.to_vec() }
+    }
+
+    /// Builds the basic filter of `block`: every non-empty output script which
+    /// is not an `OP_RETURN`, and every script spent by its inputs, which are
+    /// looked up through `script_for_coin`
+    pub fn new_script_filter<F>(block: &Block, mut script_for_coin: F) -> Result<BlockFilter, Error>
+        where F: FnMut(&TxOutRef) -> Option<Script>
+    {
+        let mut elements = HashSet::new();
+        for tx in &block.txdata {
+            for output in &tx.output {
+                if !output.script_pubkey.is_empty() && !output.script_pubkey.is_op_return() {
+                    elements.insert(output.script_pubkey.clone().into_vec());
+                }
+            }
+        }
+        // the coinbase spends nothing
+        for tx in block.txdata.iter().skip(1) {
+            for input in &tx.input {
+                let outref = TxOutRef { txid: input.prev_hash, index: input.prev_index as usize };
+                match script_for_coin(&outref) {
+                    Some(script) => if !script.is_empty() {
+                        elements.insert(script.into_vec());
+                    },
+                    None => return Err(Error::UtxoMissing(outref)),
+                }
+            }
+        }
+
+        let key = SipKey::new(&block.bitcoin_hash());
+        let n = elements.len() as u64;
+        let mut values: Vec<u64> = elements.iter().map(|e| key.map_to_range(e, n * M)).collect();
+        values.sort();
+
+        // unwrap is safe as serializing into a vector cannot fail
+        let mut writer = BitWriter { buffer: serialize(&VarInt(n)).unwrap(), offset: 0 };
+        let mut last = 0;
+        for value in values {
+            writer.write_golomb(value - last);
+            last = value;
+        }
+        Ok(BlockFilter { content: writer.buffer })
+    }
+
+    /// Computes the filter header committing to this filter and, through the
+    /// previous header, to every filter before it
+    pub fn filter_header(&self, previous_filter_header: &Sha256dHash) -> Sha256dHash {
+        let mut data = Sha256dHash::from_data(&self.content)[..].to_vec();
+        data.extend_from_slice(&previous_filter_header[..]);
+        Sha256dHash::from_data(&data)
+    }
+
+    /// Whether any of `query` may be in the filter of the block with hash
+    /// `block_hash`, up to a false positive rate of `1/M` per element
+    pub fn match_any<'a, I>(&self, block_hash: &Sha256dHash, query: I) -> Result<bool, Error>
+        where I: IntoIterator<Item=&'a [u8]>
+    {
+        let (n, mut reader) = try!(self.reader());
+        let range = try!(n.checked_mul(M).ok_or(Error::MalformedFilter));
+        let key = SipKey::new(block_hash);
+        let mut wanted: Vec<u64> = query.into_iter().map(|e| key.map_to_range(e, range)).collect();
+        if n == 0 || wanted.is_empty() {
+            return Ok(false);
+        }
+        wanted.sort();
+
+        let mut value = try!(reader.read_golomb());
+        let mut remaining = n - 1;
+        for w in wanted {
+            while value < w {
+                if remaining == 0 {
+                    return Ok(false);
+                }
+                value += try!(reader.read_golomb());
+                remaining -= 1;
+            }
+            if value == w {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Whether all of `query` may be in the filter of the block with hash
+    /// `block_hash`
+    pub fn match_all<'a, I>(&self, block_hash: &Sha256dHash, query: I) -> Result<bool, Error>
+        where I: IntoIterator<Item=&'a [u8]>
+    {
+        let (n, mut reader) = try!(self.reader());
+        let range = try!(n.checked_mul(M).ok_or(Error::MalformedFilter));
+        let key = SipKey::new(block_hash);
+        let mut wanted: Vec<u64> = query.into_iter().map(|e| key.map_to_range(e, range)).collect();
+        if wanted.is_empty() {
+            return Ok(true);
+        }
+        if n == 0 {
+            return Ok(false);
+        }
+        wanted.sort();
+        wanted.dedup();
+
+        let mut value = try!(reader.read_golomb());
+        let mut remaining = n - 1;
+        for w in wanted {
+            while value < w {
+                if remaining == 0 {
+                    return Ok(false);
+                }
+                value += try!(reader.read_golomb());
+                remaining -= 1;
+            }
+            if value != w {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Reads the element count, returning it with a reader over the coded set
+    fn reader(&self) -> Result<(u64, BitReader), Error> {
+        let mut decoder = RawDecoder::new(Cursor::new(&self.content[..]));
+        let n: VarInt = try!(ConsensusDecodable::consensus_decode(&mut decoder).map_err(|_| Error::MalformedFilter));
+        let offset = decoder.into_inner().position() as usize;
+        Ok((n.0, BitReader { buffer: &self.content[offset..], offset: 0 }))
+    }
+}
+
+/// The SipHash key of a block's filter, the first 16 bytes of its hash
+struct SipKey {
+    k0: u64,
+    k1: u64,
+}
+
+impl SipKey {
+    fn new(block_hash: &Sha256dHash) -> SipKey {
+        let mut k0 = 0;
+        let mut k1 = 0;
+        for i in 0..8 {
+            k0 |= (block_hash[i] as u64) << (8 * i);
+            k1 |= (block_hash[8 + i] as u64) << (8 * i);
+        }
+        SipKey { k0: k0, k1: k1 }
+    }
+
+    /// Hashes `element` uniformly into `[0, range)`
+    #[allow(deprecated)]
+    fn map_to_range(&self, element: &[u8], range: u64) -> u64 {
+        let mut hasher = SipHasher::new_with_keys(self.k0, self.k1);
+        hasher.write(element);
+        mul_high(hasher.finish(), range)
+    }
+}
+
+/// The high 64 bits of the 128-bit product `a * b`
+fn mul_high(a: u64, b: u64) -> u64 {
+    let (a_hi, a_lo) = (a >> 32, a & 0xffffffff);
+    let (b_hi, b_lo) = (b >> 32, b & 0xffffffff);
+    let lo_lo = a_lo * b_lo;
+    let lo_hi = a_lo * b_hi;
+    let hi_lo = a_hi * b_lo;
+    let hi_hi = a_hi * b_hi;
+    // the carry out of the low 64 bits
+    let mid = (lo_lo >> 32) + (lo_hi & 0xffffffff) + (hi_lo & 0xffffffff);
+    hi_hi + (lo_hi >> 32) + (hi_lo >> 32) + (mid >> 32)
+}
+
+/// Writes bits most significant first
+struct BitWriter {
+    buffer: Vec<u8>,
+    /// Number of bits used in the last byte of `buffer`, 0 meaning it is full
+    offset: u8,
+}
+
+impl BitWriter {
+    fn write_bit(&mut self, bit: bool) {
+        if self.offset == 0 {
+            self.buffer.push(0);
+        }
+        if bit {
+            // unwrap is safe as a byte was pushed if needed
+            *self.buffer.last_mut().unwrap() |= 0x80 >> self.offset;
+        }
+        self.offset = (self.offset + 1) % 8;
+    }
+
+    /// Writes `value >> P` in unary followed by its low `P` bits
+    fn write_golomb(&mut self, value: u64) {
+        for _ in 0..(value >> P) {
+            self.write_bit(true);
+        }
+        self.write_bit(false);
+        for i in (0..P).rev() {
+            self.write_bit((value >> i) & 1 == 1);
+        }
+    }
+}
+
+/// Reads bits most significant first
+struct BitReader<'a> {
+    buffer: &'a [u8],
+    /// Number of bits already read from the first byte of `buffer`
+    offset: u8,
+}
+
+impl<'a> BitReader<'a> {
+    fn read_bit(&mut self) -> Result<bool, Error> {
+        if self.buffer.is_empty() {
+            return Err(Error::MalformedFilter);
+        }
+        let bit = self.buffer[0] & (0x80 >> self.offset) != 0;
+        self.offset += 1;
+        if self.offset == 8 {
+            self.buffer = &self.buffer[1..];
+            self.offset = 0;
+        }
+        Ok(bit)
+    }
+
+    fn read_golomb(&mut self) -> Result<u64, Error> {
+        let mut quotient = 0u64;
+        while try!(self.read_bit()) {
+            quotient += 1;
+        }
+        let mut remainder = 0u64;
+        for _ in 0..P {
+            remainder = (remainder << 1) | try!(self.read_bit()) as u64;
+        }
+        Ok((quotient << P) | remainder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use blockdata::constants::genesis_block;
+    use blockdata::script::Script;
+    use network::constants::Network;
+    use network::serialize::BitcoinHash;
+    use util::hash::Sha256dHash;
+    use super::*;
+
+    #[test]
+    fn testnet_genesis_filter() {
+        // BIP158 test vectors, testnet block 0
+        let block = genesis_block(Network::Testnet);
+        let filter = BlockFilter::new_script_filter(&block, |_| None).unwrap();
+        assert_eq!(filter.content, vec![0x01, 0x9d, 0xfc, 0xa8]);
+        assert_eq!(filter.filter_header(&Default::default()),
+                   Sha256dHash::from_hex("21584579b7eb08997773e5aeff3a7f932700042d0ed2a6129012b7d7ae81b750").unwrap());
+
+        let hash = block.bitcoin_hash();
+        let script = &block.txdata[0].output[0].script_pubkey[..];
+        let other: &[u8] = &[0x51];
+        assert!(filter.match_any(&hash, vec![other, script]).unwrap());
+        assert!(!filter.match_any(&hash, vec![other]).unwrap());
+        assert!(filter.match_all(&hash, vec![script, script]).unwrap());
+        assert!(!filter.match_all(&hash, vec![script, other]).unwrap());
+        // the filter is keyed by the block hash
+        assert!(!filter.match_any(&Default::default(), vec![script]).unwrap());
+    }
+
+    #[test]
+    fn mul_high_matches_wide_multiply() {
+        assert_eq!(mul_high(0, 0xffffffffffffffff), 0);
+        assert_eq!(mul_high(1 << 32, 1 << 32), 1);
+        assert_eq!(mul_high(0xffffffffffffffff, 2), 1);
+        assert_eq!(mul_high(0xffffffffffffffff, 0xffffffffffffffff), 0xfffffffffffffffe);
+        assert_eq!(mul_high(0x0123456789abcdef, 0xfedcba9876543210), 0x0121fa00ad77d742);
+    }
+
+    #[test]
+    fn filter_many_scripts() {
+        let mut block = genesis_block(Network::Bitcoin);
+        let mut tx = block.txdata[0].clone();
+        let mut scripts = vec![];
+        for i in 0..300u32 {
+            let mut output = tx.output[0].clone();
+            output.script_pubkey = Script::from(vec![(i >> 8) as u8, i as u8, 0xac]);
+            scripts.push(output.script_pubkey.clone());
+            tx.output.push(output);
+        }
+        // neither empty nor OP_RETURN scripts are included
+        tx.output[0].script_pubkey = Script::new();
+        let mut op_return = tx.output[1].clone();
+        op_return.script_pubkey = Script::from(vec![0x6a, 0x01, 0x01]);
+        tx.output.push(op_return.clone());
+        // a spending transaction, whose spent script must be looked up
+        let mut spend = tx.clone();
+        spend.input[0].prev_hash = tx.txid();
+        spend.input[0].prev_index = 7;
+        spend.output.truncate(1);
+        block.txdata = vec![tx, spend];
+
+        match BlockFilter::new_script_filter(&block, |_| None) {
+            Err(Error::UtxoMissing(ref outref)) => assert_eq!(outref.index, 7),
+            x => panic!("unexpected result {:?}", x),
+        }
+        let spent = Script::from(vec![0x51]);
+        let filter = BlockFilter::new_script_filter(&block, |_| Some(spent.clone())).unwrap();
+        assert_eq!(filter.content[..3], [0xfd, 0x2d, 0x01]);
+
+        let hash = block.bitcoin_hash();
+        let mut all: Vec<&[u8]> = scripts.iter().map(|s| &s[..]).collect();
+        all.push(&spent[..]);
+        assert!(filter.match_all(&hash, all.clone()).unwrap());
+        for s in &all {
+            assert!(filter.match_any(&hash, vec![*s]).unwrap());
+        }
+        assert!(!filter.match_any(&hash, vec![&[][..], &op_return.script_pubkey[..]]).unwrap());
+
+        let truncated = BlockFilter::new(&filter.content[..filter.content.len() / 2]);
+        assert_eq!(truncated.match_all(&hash, all.clone()), Err(Error::MalformedFilter));
+
+        // an element count whose range does not fit in 64 bits
+        let huge = BlockFilter::new(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00]);
+        assert_eq!(huge.match_any(&hash, all.clone()), Err(Error::MalformedFilter));
+        assert_eq!(huge.match_all(&hash, all), Err(Error::MalformedFilter));
+    }
+}
@@ -18,7 +18,8 @@
 
 use std::default::Default;
 use std::io::Cursor;
-use std::{error, fmt};
+use std::ops::Range;
+use std::{cmp, error, fmt};
 use std::str::FromStr;
 use std::string::ToString;
 use serde::{Serialize, Deserialize, Serializer, Deserializer};
@@ -32,6 +33,7 @@ use secp256k1::key::{PublicKey, SecretKey};
 use secp256k1::{self, Secp256k1};
 
 use network::constants::Network;
+use util::address::{Address, AddressType};
 use util::base58;
 
 #[cfg(feature="fuzztarget")]      use util::sha2::{Sha256, Sha512};
@@ -317,6 +319,38 @@ impl ExtendedPubKey {
         })
     }
 
+    /// Derives the non-hardened children `i` in `range` and builds an address
+    /// of type `address_type` for each of them
+    pub fn derive_addresses(&self, secp: &Secp256k1, network: Network, range: Range<u32>,
+                            address_type: AddressType) -> Result<Vec<Address>, Error> {
+        // check the whole range before allocating for it
+        if range.start < range.end && range.end > (1 << 31) {
+            let first_invalid = cmp::max(range.start, 1 << 31);
+            return Err(Error::InvalidChildNumber(ChildNumber::Normal(first_invalid)));
+        }
+        let mut ret = Vec::with_capacity(range.len());
+        for i in range {
+            let child = try!(self.ckd_pub(secp, ChildNumber::Normal(i)));
+            ret.push(child_address(&child, network, address_type));
+        }
+        Ok(ret)
+    }
+
+    /// Treating this key as an account key, returns an iterator over the
+    /// addresses of its receive (`change == false`) or change chain, i.e. of
+    /// the keys `0/i` or `1/i` below it, for every non-hardened `i`
+    pub fn address_iter<'a>(&self, secp: &'a Secp256k1, network: Network, change: bool,
+                            address_type: AddressType) -> Result<AddressIterator<'a>, Error> {
+        let chain = try!(self.ckd_pub(secp, ChildNumber::Normal(change as u32)));
+        Ok(AddressIterator {
+            secp: secp,
+            chain: chain,
+            network: network,
+            address_type: address_type,
+            next_index: 0
+        })
+    }
+
     /// Returns the HASH160 of the chaincode
     pub fn identifier(&self) -> [u8; 20] {
         let mut sha2_res = [0; 32];
@@ -339,6 +373,51 @@ impl ExtendedPubKey {
     }
 }
 
+/// Checks that a decoded master key (depth 0) has neither a parent nor a child number,
+/// which would make it impossible to derive from consistently
+fn check_master_structure(depth: u8, parent_fingerprint: &Fingerprint, child_number: u32)
+                          -> Result<(), base58::Error> {
+    if depth == 0 && (parent_fingerprint[..] != [0; 4] || child_number != 0) {
+        Err(base58::Error::InvalidExtendedKeyStructure)
+    } else {
+        Ok(())
+    }
+}
+
+fn child_address(child: &ExtendedPubKey, network: Network, address_type: AddressType) -> Address {
+    match address_type {
+        AddressType::P2pkh => Address::p2pkh(&child.public_key, network),
+        AddressType::P2shP2wpkh => Address::p2shwpkh(&child.public_key, network),
+        AddressType::P2wpkh => Address::p2wpkh(&child.public_key, network),
+    }
+}
+
+/// An iterator over `(index, address)` for the non-hardened children of a
+/// chain key, see `ExtendedPubKey::address_iter`. Indices whose derivation
+/// is invalid are skipped, as BIP32 specifies.
+pub struct AddressIterator<'a> {
+    secp: &'a Secp256k1,
+    chain: ExtendedPubKey,
+    network: Network,
+    address_type: AddressType,
+    next_index: u32
+}
+
+impl<'a> Iterator for AddressIterator<'a> {
+    type Item = (u32, Address);
+
+    fn next(&mut self) -> Option<(u32, Address)> {
+        while self.next_index < (1 << 31) {
+            let i = self.next_index;
+            self.next_index += 1;
+            if let Ok(child) = self.chain.ckd_pub(self.secp, ChildNumber::Normal(i)) {
+                return Some((i, child_address(&child, self.network, self.address_type)));
+            }
+        }
+        None
+    }
+}
+
 impl ToString for ExtendedPrivKey {
     fn to_string(&self) -> String {
         let mut ret = [0; 78];
@@ -377,6 +456,7 @@ impl FromStr for ExtendedPrivKey {
         let cn_int = Cursor::new(&data[9..13]).read_u32::<BigEndian>().unwrap();
         let child_number = if cn_int < (1 << 31) { ChildNumber::Normal(cn_int) }
                            else { ChildNumber::Hardened(cn_int - (1 << 31)) };
+        try!(check_master_structure(data[4], &Fingerprint::from(&data[5..9]), cn_int));
 
         Ok(ExtendedPrivKey {
             network: if &data[0..4] == [0x04u8, 0x88, 0xAD, 0xE4] {
@@ -434,6 +514,7 @@ impl FromStr for ExtendedPubKey {
         let cn_int = Cursor::new(&data[9..13]).read_u32::<BigEndian>().unwrap();
         let child_number = if cn_int < (1 << 31) { ChildNumber::Normal(cn_int) }
                            else { ChildNumber::Hardened(cn_int - (1 << 31)) };
+        try!(check_master_structure(data[4], &Fingerprint::from(&data[5..9]), cn_int));
 
         Ok(ExtendedPubKey {
             network: if &data[0..4] == [0x04u8, 0x88, 0xB2, 0x1E] {
@@ -462,9 +543,12 @@ mod tests {
     use secp256k1::Secp256k1;
     use serialize::hex::FromHex;
 
-    use network::constants::Network::{self, Bitcoin};
+    use network::constants::Network::{self, Bitcoin, Testnet};
+
+    use util::address::{Address, AddressType};
+    use util::base58;
 
-    use super::{ChildNumber, ExtendedPrivKey, ExtendedPubKey};
+    use super::{ChildNumber, Error, ExtendedPrivKey, ExtendedPubKey, Fingerprint};
     use super::ChildNumber::{Hardened, Normal};
 
     fn test_path(secp: &Secp256k1,
@@ -572,6 +656,71 @@ mod tests {
                   "xpub6FnCn6nSzZAw5Tw7cgR9bi15UV96gLZhjDstkXXxvCLsUXBGXPdSnLFbdpq8p9HmGsApME5hQTZ3emM2rnY5agb9rXpVGyy3bdW6EEgAtqt");
     }
 
+    #[test]
+    fn test_error_into_util_error() {
+        use std::error::Error;
+        use util;
+
+        let secp = Secp256k1::new();
+        let sk = ExtendedPrivKey::new_master(&secp, Bitcoin, &[0; 32]).unwrap();
+        let pk = ExtendedPubKey::from_private(&secp, &sk);
+        let err: util::Error = pk.ckd_pub(&secp, Hardened(0)).unwrap_err().into();
+        match err {
+            util::Error::Bip32(super::Error::CannotDeriveFromHardenedKey) => {},
+            ref x => panic!("unexpected error {:?}", x),
+        }
+        assert_eq!(err.description(), "cannot derive hardened key from public key");
+        assert!(err.cause().is_some());
+    }
+
+    #[test]
+    fn test_master_structure() {
+        let secp = Secp256k1::new();
+        let seed = "000102030405060708090a0b0c0d0e0f".from_hex().unwrap();
+        let sk = ExtendedPrivKey::new_master(&secp, Bitcoin, &seed).unwrap();
+        let pk = ExtendedPubKey::from_private(&secp, &sk);
+
+        let mut bad_sk = sk;
+        bad_sk.child_number = Normal(1);
+        assert_eq!(ExtendedPrivKey::from_str(&bad_sk.to_string()),
+                   Err(base58::Error::InvalidExtendedKeyStructure));
+
+        let mut bad_pk = pk;
+        bad_pk.child_number = Hardened(0);
+        assert_eq!(ExtendedPubKey::from_str(&bad_pk.to_string()),
+                   Err(base58::Error::InvalidExtendedKeyStructure));
+
+        let mut bad_pk = pk;
+        bad_pk.parent_fingerprint = Fingerprint::from(&[1, 2, 3, 4][..]);
+        assert_eq!(ExtendedPubKey::from_str(&bad_pk.to_string()),
+                   Err(base58::Error::InvalidExtendedKeyStructure));
+
+        // the same fields are fine at nonzero depth
+        bad_pk.depth = 1;
+        assert_eq!(ExtendedPubKey::from_str(&bad_pk.to_string()), Ok(bad_pk));
+    }
+
+    #[test]
+    fn test_address_iter() {
+        let secp = Secp256k1::new();
+        let account = ExtendedPubKey::from_str("xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V").unwrap();
+
+        let receive = account.ckd_pub(&secp, Normal(0)).unwrap();
+        let expected = receive.derive_addresses(&secp, Bitcoin, 0..5, AddressType::P2wpkh).unwrap();
+        let iter = account.address_iter(&secp, Bitcoin, false, AddressType::P2wpkh).unwrap();
+        let addrs: Vec<(u32, Address)> = iter.take(5).collect();
+        assert_eq!(addrs, (0..5).zip(expected).collect::<Vec<_>>());
+
+        // BIP84 first change address
+        let mut change = account.address_iter(&secp, Bitcoin, true, AddressType::P2wpkh).unwrap();
+        let (i, addr) = change.next().unwrap();
+        assert_eq!(i, 0);
+        assert_eq!(addr.to_string(), "bc1q8c6fshw2dlwun7ekn9qwf37cu2rn755upcp6el");
+
+        let mut p2pkh = account.address_iter(&secp, Testnet, false, AddressType::P2pkh).unwrap();
+        assert_eq!(p2pkh.nth(3).unwrap().1, Address::p2pkh(&receive.ckd_pub(&secp, Normal(3)).unwrap().public_key, Testnet));
+    }
+
     #[test]
     pub fn encode_decode_childnumber() {
         serde_round_trip!(Normal(0));
@@ -581,5 +730,55 @@ mod tests {
         serde_round_trip!(Hardened(1));
         serde_round_trip!(Hardened((1 << 31) - 1));
     }
+
+    #[test]
+    fn test_derive_addresses() {
+        let secp = Secp256k1::new();
+        // BIP84 account m/84'/0'/0' of the "abandon ... about" mnemonic, as an xpub
+        let account = ExtendedPubKey::from_str("xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V").unwrap();
+        let receive = account.ckd_pub(&secp, Normal(0)).unwrap();
+
+        let addrs = receive.derive_addresses(&secp, Bitcoin, 0..5, AddressType::P2wpkh).unwrap();
+        assert_eq!(addrs.len(), 5);
+        assert_eq!(addrs[0].to_string(), "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu");
+        assert_eq!(addrs[1].to_string(), "bc1qnjg0jd8228aq7egyzacy8cys3knf9xvrerkf9g");
+        for (i, addr) in addrs.iter().enumerate() {
+            let child = receive.ckd_pub(&secp, Normal(i as u32)).unwrap();
+            assert_eq!(*addr, Address::p2wpkh(&child.public_key, Bitcoin));
+        }
+
+        let child = receive.ckd_pub(&secp, Normal(3)).unwrap();
+        let p2pkh = receive.derive_addresses(&secp, Bitcoin, 3..4, AddressType::P2pkh).unwrap();
+        assert_eq!(p2pkh, vec![Address::p2pkh(&child.public_key, Bitcoin)]);
+        let p2sh = receive.derive_addresses(&secp, Bitcoin, 3..4, AddressType::P2shP2wpkh).unwrap();
+        assert_eq!(p2sh, vec![Address::p2shwpkh(&child.public_key, Bitcoin)]);
+
+        assert!(receive.derive_addresses(&secp, Bitcoin, 5..5, AddressType::P2wpkh).unwrap().is_empty());
+        assert_eq!(receive.derive_addresses(&secp, Bitcoin, (1 << 31)..(1 << 31) + 1, AddressType::P2wpkh),
+                   Err(Error::InvalidChildNumber(Normal(1 << 31))));
+        assert_eq!(receive.derive_addresses(&secp, Bitcoin, 0..u32::max_value(), AddressType::P2wpkh),
+                   Err(Error::InvalidChildNumber(Normal(1 << 31))));
+    }
 }
 
+#[cfg(all(test, feature = "unstable"))]
+mod benches {
+    use std::str::FromStr;
+
+    use secp256k1::Secp256k1;
+    use test::Bencher;
+
+    use network::constants::Network::Bitcoin;
+    use util::address::AddressType;
+    use super::ExtendedPubKey;
+
+    #[bench]
+    pub fn bench_address_iter_1000(bh: &mut Bencher) {
+        let secp = Secp256k1::new();
+        let account = ExtendedPubKey::from_str("xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V").unwrap();
+        bh.iter(|| {
+            let iter = account.address_iter(&secp, Bitcoin, false, AddressType::P2wpkh).unwrap();
+            assert_eq!(iter.take(1000).count(), 1000);
+        });
+    }
+}
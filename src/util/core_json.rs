@@ -0,0 +1,401 @@
+// Rust Bitcoin Library
+// Written in 2018 by
+//     The Rust Bitcoin developers
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Bitcoin Core JSON
+//!
+//! The JSON representation of a transaction produced by bitcoin-core's
+//! `decoderawtransaction` RPC (as of 0.21), for interoperability with
+//! tooling which consumes it.
+//!
+
+use serde;
+use serialize::hex::ToHex;
+use strason::Json;
+
+use blockdata::opcodes;
+use blockdata::script::{Instruction, Script, ScriptNum, MAX_SCRIPT_SIZE};
+use blockdata::transaction::{SigHashType, Transaction, TxIn, TxOut};
+use network::constants::Network;
+use util::address::Address;
+use util::decimal::UDecimal;
+
+/// A transaction as decoded by bitcoin-core's `decoderawtransaction`. The
+/// network is needed to render output addresses.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct DecodedTransaction<'a> {
+    tx: &'a Transaction,
+    network: Network
+}
+
+impl<'a> DecodedTransaction<'a> {
+    /// Wraps a transaction for serialization
+    pub fn new(tx: &'a Transaction, network: Network) -> DecodedTransaction<'a> {
+        DecodedTransaction {
+            tx: tx,
+            network: network
+        }
+    }
+
+    /// Builds the JSON object, with its keys in the same order as bitcoin-core
+    pub fn to_json(&self) -> Json {
+        let tx = self.tx;
+        let weight = tx.get_weight();
+        let vin: Vec<Json> = tx.input.iter().map(|txin| input_json(tx, txin)).collect();
+        let vout: Vec<Json> = tx.output.iter().enumerate()
+                                .map(|(n, txout)| output_json(n, txout, self.network))
+                                .collect();
+        Json::from(vec![
+            ("txid".to_owned(), Json::from(tx.txid().to_string())),
+            ("hash".to_owned(), Json::from(tx.wtxid().to_string())),
+            ("version".to_owned(), Json::from(tx.version as i32)),
+            ("size".to_owned(), Json::from(tx.get_size())),
+            ("vsize".to_owned(), Json::from(tx.get_vsize())),
+            ("weight".to_owned(), Json::from(weight)),
+            ("locktime".to_owned(), Json::from(tx.lock_time)),
+            ("vin".to_owned(), Json::from(vin)),
+            ("vout".to_owned(), Json::from(vout))
+        ])
+    }
+}
+
+impl<'a> serde::Serialize for DecodedTransaction<'a> {
+    fn serialize<S>(&self, s: &mut S) -> Result<(), S::Error>
+        where S: serde::Serializer,
+    {
+        serde::Serialize::serialize(&self.to_json(), s)
+    }
+}
+
+fn input_json(tx: &Transaction, txin: &TxIn) -> Json {
+    let mut ret = vec![];
+    if tx.is_coin_base() {
+        ret.push(("coinbase".to_owned(), Json::from(txin.script_sig[..].to_hex())));
+    } else {
+        let script_sig = vec![
+            ("asm".to_owned(), Json::from(core_asm(&txin.script_sig, true))),
+            ("hex".to_owned(), Json::from(txin.script_sig[..].to_hex()))
+        ];
+        ret.push(("txid".to_owned(), Json::from(txin.prev_hash.to_string())));
+        ret.push(("vout".to_owned(), Json::from(txin.prev_index)));
+        ret.push(("scriptSig".to_owned(), Json::from(script_sig)));
+    }
+    if !txin.witness.is_empty() {
+        let witness: Vec<Json> = txin.witness.iter().map(|elem| Json::from(elem.to_hex())).collect();
+        ret.push(("txinwitness".to_owned(), Json::from(witness)));
+    }
+    ret.push(("sequence".to_owned(), Json::from(txin.sequence)));
+    Json::from(ret)
+}
+
+fn output_json(n: usize, txout: &TxOut, network: Network) -> Json {
+    let script = &txout.script_pubkey;
+    let mut script_pubkey = vec![
+        ("asm".to_owned(), Json::from(core_asm(script, false))),
+        ("hex".to_owned(), Json::from(script[..].to_hex()))
+    ];
+    let (ty, destinations) = classify(script, network);
+    // bitcoin-core does not give an address for bare public keys
+    if ty != "pubkey" && !destinations.is_empty() {
+        let req_sigs = match script.multisig_params() {
+            Some((m, _, _)) => m,
+            None => 1
+        };
+        script_pubkey.push(("reqSigs".to_owned(), Json::from(req_sigs)));
+        script_pubkey.push(("type".to_owned(), Json::from(ty)));
+        let addresses: Vec<Json> = destinations.iter().map(|addr| Json::from(addr.to_string())).collect();
+        script_pubkey.push(("addresses".to_owned(), Json::from(addresses)));
+    } else {
+        script_pubkey.push(("type".to_owned(), Json::from(ty)));
+    }
+
+    Json::from(vec![
+        ("value".to_owned(), serialize_value(txout.value)),
+        ("n".to_owned(), Json::from(n)),
+        ("scriptPubKey".to_owned(), Json::from(script_pubkey))
+    ])
+}
+
+/// Renders an amount in bitcoin with all eight decimal places, as bitcoin-core does
+fn serialize_value(value: u64) -> Json {
+    Json::from_serialize(&UDecimal::new(value, 8)).unwrap()
+}
+
+/// Determines bitcoin-core's name for the type of a script pubkey, along with
+/// the addresses it pays to. Witness programs other than version 0 have no
+/// address form here.
+fn classify(script: &Script, network: Network) -> (&'static str, Vec<Address>) {
+    if script.is_p2sh() || script.is_p2pkh() || script.is_v0_p2wpkh() || script.is_v0_p2wsh() {
+        let ty = if script.is_p2sh() {
+            "scripthash"
+        } else if script.is_p2pkh() {
+            "pubkeyhash"
+        } else if script.is_v0_p2wpkh() {
+            "witness_v0_keyhash"
+        } else {
+            "witness_v0_scripthash"
+        };
+        // unwrap is safe as all of these have an address form
+        return (ty, vec![Address::from_script(script, network).unwrap()]);
+    }
+
+    match (script.witness_version(), script.witness_program()) {
+        (Some(0), _) => return ("nonstandard", vec![]),
+        (Some(1), Some(program)) if program.len() == 32 => return ("witness_v1_taproot", vec![]),
+        (Some(_), _) => return ("witness_unknown", vec![]),
+        (None, _) => {}
+    }
+
+    if script.is_op_return() && is_push_only(&script[1..]) {
+        ("nulldata", vec![])
+    } else if script.is_p2pk() {
+        ("pubkey", vec![])
+    } else if let Some((_, _, keys)) = script.multisig_params() {
+        let addresses = keys.iter().map(|pk| {
+            if pk.compressed {
+                Address::p2pkh(&pk.key, network)
+            } else {
+                Address::p2upkh(&pk.key, network)
+            }
+        }).collect();
+        ("multisig", addresses)
+    } else {
+        ("nonstandard", vec![])
+    }
+}
+
+/// Whether a script consists only of pushes, counting `OP_RESERVED` as a push
+/// as bitcoin-core does
+fn is_push_only(data: &[u8]) -> bool {
+    Script::from(data.to_vec()).into_iter().all(|instruction| match instruction {
+        Instruction::PushBytes(_) => true,
+        Instruction::Op(op) => op as u8 <= opcodes::All::OP_PUSHNUM_16 as u8,
+        Instruction::Error(_) => false
+    })
+}
+
+/// Formats a script the way bitcoin-core's `ScriptToAsmStr` does: pushes of
+/// up to four bytes as numbers, longer pushes as hex, and other opcodes by
+/// name. Pushes in scriptSigs which look like signatures have their sighash
+/// type decoded.
+fn core_asm(script: &Script, decode_sighash: bool) -> String {
+    // bitcoin-core's `IsUnspendable`, which unlike `is_provably_unspendable`
+    // does not consider scripts starting with an illegal opcode
+    let unspendable = script.is_op_return() || script.len() > MAX_SCRIPT_SIZE;
+    let mut ret = String::new();
+    for instruction in script {
+        if !ret.is_empty() {
+            ret.push(' ');
+        }
+        match instruction {
+            Instruction::PushBytes(data) if data.len() <= 4 => {
+                // unwrap is safe as four bytes is the default maximum
+                let n = ScriptNum::from_slice(data, false, 4).unwrap();
+                ret.push_str(&n.value().to_string());
+            }
+            Instruction::PushBytes(data) => {
+                if decode_sighash && !unspendable && is_valid_signature_encoding(data) {
+                    let (&hash_ty, sig) = data.split_last().unwrap();
+                    if let Some(name) = sighash_name(hash_ty) {
+                        ret.push_str(&sig.to_hex());
+                        ret.push('[');
+                        ret.push_str(name);
+                        ret.push(']');
+                        continue;
+                    }
+                }
+                ret.push_str(&data.to_hex());
+            }
+            Instruction::Op(op) => ret.push_str(&opcode_name(op)),
+            Instruction::Error(_) => {
+                ret.push_str("[error]");
+                break;
+            }
+        }
+    }
+    ret
+}
+
+/// bitcoin-core's name for a non-push opcode
+fn opcode_name(op: opcodes::All) -> String {
+    match op.classify() {
+        opcodes::Class::PushNum(n) => n.to_string(),
+        _ => match op {
+            opcodes::All::OP_NOP2 => "OP_CHECKLOCKTIMEVERIFY".to_owned(),
+            opcodes::All::OP_NOP3 => "OP_CHECKSEQUENCEVERIFY".to_owned(),
+            opcodes::All::OP_RETURN_186 => "OP_CHECKSIGADD".to_owned(),
+            opcodes::All::OP_RETURN_255 => "OP_INVALIDOPCODE".to_owned(),
+            _ if op as u8 > opcodes::All::OP_NOP10 as u8 => "OP_UNKNOWN".to_owned(),
+            _ => format!("{:?}", op)
+        }
+    }
+}
+
+/// bitcoin-core's name for a standard sighash type byte
+fn sighash_name(hash_ty: u8) -> Option<&'static str> {
+    let parsed_ty = SigHashType::from_u32(hash_ty as u32);
+    if parsed_ty.as_u32() != hash_ty as u32 {
+        return None;
+    }
+    Some(match parsed_ty {
+        SigHashType::All => "ALL",
+        SigHashType::None => "NONE",
+        SigHashType::Single => "SINGLE",
+        SigHashType::AllPlusAnyoneCanPay => "ALL|ANYONECANPAY",
+        SigHashType::NonePlusAnyoneCanPay => "NONE|ANYONECANPAY",
+        SigHashType::SinglePlusAnyoneCanPay => "SINGLE|ANYONECANPAY"
+    })
+}
+
+/// Whether `sig` is a strict DER signature followed by a sighash byte, as
+/// required by BIP66
+fn is_valid_signature_encoding(sig: &[u8]) -> bool {
+    if sig.len() < 9 || sig.len() > 73 {
+        return false;
+    }
+    // A compound structure covering the whole signature but the sighash byte
+    if sig[0] != 0x30 || sig[1] as usize != sig.len() - 3 {
+        return false;
+    }
+    let len_r = sig[3] as usize;
+    if 5 + len_r >= sig.len() {
+        return false;
+    }
+    let len_s = sig[5 + len_r] as usize;
+    if len_r + len_s + 7 != sig.len() {
+        return false;
+    }
+    // Both integers must be positive and minimally encoded
+    for &(start, len) in &[(2, len_r), (4 + len_r, len_s)] {
+        if sig[start] != 0x02 || len == 0 || sig[start + 2] & 0x80 != 0 {
+            return false;
+        }
+        if len > 1 && sig[start + 2] == 0x00 && sig[start + 3] & 0x80 == 0 {
+            return false;
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use strason::Json;
+
+    use blockdata::transaction::Transaction;
+    use network::constants::Network;
+    use network::serialize::deserialize;
+    use util::misc::hex_bytes;
+    use super::{core_asm, DecodedTransaction};
+
+    #[test]
+    fn decoded_p2pk_spend() {
+        // The first transaction between two people, from block 170
+        let tx: Transaction = deserialize(&hex_bytes("0100000001c997a5e56e104102fa209c6a852dd90660a20b2d9c352423edce25857fcd3704000000004847304402204e45e16932b8af514961a1d3a1a25fdf3f4f7732e9d624c6c61548ab5fb8cd410220181522ec8eca07de4860a4acdd12909d831cc56cbbac4622082221a8768d1d0901ffffffff0200ca9a3b00000000434104ae1a62fe09c5f51b13905f07f06b99a2f7159b2225f374cd378d71302fa28414e7aab37397f554a7df5f142c21c1b7303b8a0626f1baded5c72a704f7e6cd84cac00286bee0000000043410411db93e1dcdb8a016b49840f8c53bc1eb68a382e97b1482ecad7b148a6909a5cb2e0eaddfb84ccf9744464f82e160bfa9b8b64f9d4c03f999b8643f656b412a3ac00000000").unwrap()).unwrap();
+        let core = Json::from_str(r#"{
+          "txid": "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16",
+          "hash": "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16",
+          "version": 1,
+          "size": 275,
+          "vsize": 275,
+          "weight": 1100,
+          "locktime": 0,
+          "vin": [
+            {
+              "txid": "0437cd7f8525ceed2324359c2d0ba26006d92d856a9c20fa0241106ee5a597c9",
+              "vout": 0,
+              "scriptSig": {
+                "asm": "304402204e45e16932b8af514961a1d3a1a25fdf3f4f7732e9d624c6c61548ab5fb8cd410220181522ec8eca07de4860a4acdd12909d831cc56cbbac4622082221a8768d1d09[ALL]",
+                "hex": "47304402204e45e16932b8af514961a1d3a1a25fdf3f4f7732e9d624c6c61548ab5fb8cd410220181522ec8eca07de4860a4acdd12909d831cc56cbbac4622082221a8768d1d0901"
+              },
+              "sequence": 4294967295
+            }
+          ],
+          "vout": [
+            {
+              "value": 10.00000000,
+              "n": 0,
+              "scriptPubKey": {
+                "asm": "04ae1a62fe09c5f51b13905f07f06b99a2f7159b2225f374cd378d71302fa28414e7aab37397f554a7df5f142c21c1b7303b8a0626f1baded5c72a704f7e6cd84c OP_CHECKSIG",
+                "hex": "4104ae1a62fe09c5f51b13905f07f06b99a2f7159b2225f374cd378d71302fa28414e7aab37397f554a7df5f142c21c1b7303b8a0626f1baded5c72a704f7e6cd84cac",
+                "type": "pubkey"
+              }
+            },
+            {
+              "value": 40.00000000,
+              "n": 1,
+              "scriptPubKey": {
+                "asm": "0411db93e1dcdb8a016b49840f8c53bc1eb68a382e97b1482ecad7b148a6909a5cb2e0eaddfb84ccf9744464f82e160bfa9b8b64f9d4c03f999b8643f656b412a3 OP_CHECKSIG",
+                "hex": "410411db93e1dcdb8a016b49840f8c53bc1eb68a382e97b1482ecad7b148a6909a5cb2e0eaddfb84ccf9744464f82e160bfa9b8b64f9d4c03f999b8643f656b412a3ac",
+                "type": "pubkey"
+              }
+            }
+          ]
+        }"#).unwrap();
+
+        let decoded = DecodedTransaction::new(&tx, Network::Bitcoin);
+        assert_eq!(decoded.to_json(), core);
+        assert_eq!(Json::from_serialize(&decoded).unwrap(), core);
+    }
+
+    #[test]
+    fn decoded_segwit() {
+        // The signed native P2WPKH example from BIP143
+        let tx: Transaction = deserialize(&hex_bytes("01000000000102fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f00000000494830450221008b9d1dc26ba6a9cb62127b02742fa9d754cd3bebf337f7a55d114c8e5cdd30be022040529b194ba3f9281a99f2b1c0a19c0489bc22ede944ccf4ecbab4cc618ef3ed01eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac000247304402203609e17b84f6a7d30c80bfa610b5b4542f32a8a0d5447a12fb1366d7f01cc44a0220573a954c4518331561406f90300e8f3358f51928d43c212a8caed02de67eebee0121025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee635711000000").unwrap()).unwrap();
+        let json = DecodedTransaction::new(&tx, Network::Bitcoin).to_json();
+
+        assert_eq!(json["txid"].string(), Some("e8151a2af31c368a35053ddd4bdb285a8595c769a3ad83e0fa02314a602d4609"));
+        assert_eq!(json["hash"].string(), Some("c36c38370907df2324d9ce9d149d191192f338b37665a82e78e76a12c909b762"));
+        assert_eq!(json["size"].num(), Some("343"));
+        assert_eq!(json["vsize"].num(), Some("261"));
+        assert_eq!(json["weight"].num(), Some("1042"));
+        assert_eq!(json["locktime"].num(), Some("17"));
+
+        let vin = json["vin"].array().unwrap();
+        assert!(vin[0].get("txinwitness").is_none());
+        assert_eq!(vin[0]["sequence"].num(), Some("4294967278"));
+        assert_eq!(vin[1]["scriptSig"]["asm"].string(), Some(""));
+        assert_eq!(vin[1]["txinwitness"].array().unwrap().len(), 2);
+        assert_eq!(vin[1]["txinwitness"][1].string(), Some("025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee6357"));
+
+        let vout = json["vout"].array().unwrap();
+        assert_eq!(vout[0]["value"].num(), Some("1.12340000"));
+        assert_eq!(vout[0]["scriptPubKey"]["asm"].string(),
+                   Some("OP_DUP OP_HASH160 8280b37df378db99f66f85c95a783a76ac7a6d59 OP_EQUALVERIFY OP_CHECKSIG"));
+        assert_eq!(vout[0]["scriptPubKey"]["reqSigs"].num(), Some("1"));
+        assert_eq!(vout[0]["scriptPubKey"]["type"].string(), Some("pubkeyhash"));
+        assert_eq!(vout[0]["scriptPubKey"]["addresses"][0].string(), Some("1Cu32FVupVCgHkMMRJdYJugxwo2Aprgk7H"));
+        assert_eq!(vout[1]["value"].num(), Some("2.23450000"));
+    }
+
+    #[test]
+    fn core_asm_numbers_and_opcodes() {
+        use serialize::hex::FromHex;
+
+        let script = hex_script!("0051024f01034f0180b1b2babb6a");
+        assert_eq!(core_asm(&script, false),
+                   "0 1 335 -335 OP_CHECKLOCKTIMEVERIFY OP_CHECKSEQUENCEVERIFY OP_CHECKSIGADD OP_UNKNOWN OP_RETURN");
+        let truncated = hex_script!("76a914aa");
+        assert_eq!(core_asm(&truncated, false), "OP_DUP OP_HASH160 [error]");
+    }
+
+    #[test]
+    fn core_asm_sighash() {
+        use serialize::hex::FromHex;
+
+        let sig = "304402204e45e16932b8af514961a1d3a1a25fdf3f4f7732e9d624c6c61548ab5fb8cd410220181522ec8eca07de4860a4acdd12909d831cc56cbbac4622082221a8768d1d09";
+        // only a leading OP_RETURN disables sighash decoding, not other illegal opcodes
+        let script = hex_script!(&format!("6547{}01", sig));
+        assert_eq!(core_asm(&script, true), format!("OP_VERIF {}[ALL]", sig));
+        let script = hex_script!(&format!("6a47{}01", sig));
+        assert_eq!(core_asm(&script, true), format!("OP_RETURN {}01", sig));
+    }
+}
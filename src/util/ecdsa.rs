@@ -0,0 +1,153 @@
+// Rust Bitcoin Library
+// Written in 2018 by
+//     The Rust Bitcoin developers
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # ECDSA signatures
+//!
+//! ECDSA signatures as they appear in scriptSigs and witnesses: a DER
+//! encoded signature followed by a single sighash type byte.
+//!
+
+use std::{error, fmt};
+
+use secp256k1::{self, Secp256k1, Signature};
+
+use blockdata::transaction::SigHashType;
+
+/// An ECDSA signature along with the sighash type it commits to
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct EcdsaSig {
+    /// The underlying signature
+    pub sig: Signature,
+    /// The sighash type appended to the signature
+    pub hash_ty: SigHashType
+}
+
+impl EcdsaSig {
+    /// Parses a DER signature followed by its sighash type byte
+    pub fn from_slice(secp: &Secp256k1, data: &[u8]) -> Result<EcdsaSig, Error> {
+        let (hash_ty, sig) = match data.split_last() {
+            Some((&hash_ty, sig)) => (hash_ty, sig),
+            None => return Err(Error::EmptySignature)
+        };
+        let parsed_ty = SigHashType::from_u32(hash_ty as u32);
+        if parsed_ty.as_u32() != hash_ty as u32 {
+            return Err(Error::NonStandardSigHashType(hash_ty));
+        }
+        Ok(EcdsaSig {
+            sig: try!(Signature::from_der(secp, sig)),
+            hash_ty: parsed_ty
+        })
+    }
+
+    /// Serializes the signature as DER followed by its sighash type byte
+    pub fn to_vec(&self, secp: &Secp256k1) -> Vec<u8> {
+        let mut ret = self.sig.serialize_der(secp);
+        ret.push(self.hash_ty.as_u32() as u8);
+        ret
+    }
+
+    /// Replaces a high S value by its negation, as required for the
+    /// signature to be standard
+    pub fn normalize_s(&mut self, secp: &Secp256k1) {
+        self.sig.normalize_s(secp);
+    }
+}
+
+/// An ECDSA signature parsing error
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// The signature had no sighash type byte
+    EmptySignature,
+    /// The sighash type byte is not one of the six standard values
+    NonStandardSigHashType(u8),
+    /// The DER signature was malformed
+    Secp256k1(secp256k1::Error)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::EmptySignature => f.write_str("empty signature"),
+            Error::NonStandardSigHashType(ty) => write!(f, "non-standard sighash type {:#x}", ty),
+            Error::Secp256k1(ref e) => fmt::Display::fmt(e, f)
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> {
+        if let Error::Secp256k1(ref e) = *self {
+            Some(e)
+        } else {
+            None
+        }
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            Error::EmptySignature => "empty signature",
+            Error::NonStandardSigHashType(_) => "non-standard sighash type",
+            Error::Secp256k1(ref e) => error::Error::description(e)
+        }
+    }
+}
+
+impl From<secp256k1::Error> for Error {
+    fn from(e: secp256k1::Error) -> Error { Error::Secp256k1(e) }
+}
+
+#[cfg(test)]
+mod tests {
+    use secp256k1::Secp256k1;
+
+    use blockdata::transaction::SigHashType;
+    use util::misc::hex_bytes;
+    use super::{EcdsaSig, Error};
+
+    #[test]
+    fn ecdsa_sig_round_trip() {
+        let secp = Secp256k1::without_caps();
+        // 71 and 72 bytes with the sighash byte, from transactions in the chain
+        for hex in &["304402204165be9a4cbab8049e1af9723b96199bfd3e85f44c6b4c0177e3962686b26073022028f638da23fc003760861ad481ead4099312c60030d4cb57820ce4d33812a5ce01",
+                     "3045022100cb2c6b346a978ab8c61b18b5e9397755cbd17d6eb2fe0083ef32e067fa6c785a02206ce44e613f31d9a6b0517e46f3db1576e9812cc98d159bfdaf759a5014081b5c01"] {
+            let data = hex_bytes(hex).unwrap();
+            let sig = EcdsaSig::from_slice(&secp, &data).unwrap();
+            assert_eq!(sig.hash_ty, SigHashType::All);
+            assert_eq!(sig.to_vec(&secp), data);
+        }
+
+        let mut data = hex_bytes("304402204165be9a4cbab8049e1af9723b96199bfd3e85f44c6b4c0177e3962686b26073022028f638da23fc003760861ad481ead4099312c60030d4cb57820ce4d33812a5ce83").unwrap();
+        let sig = EcdsaSig::from_slice(&secp, &data).unwrap();
+        assert_eq!(sig.hash_ty, SigHashType::SinglePlusAnyoneCanPay);
+        assert_eq!(sig.to_vec(&secp), data);
+
+        *data.last_mut().unwrap() = 0x04;
+        assert_eq!(EcdsaSig::from_slice(&secp, &data), Err(Error::NonStandardSigHashType(0x04)));
+        assert_eq!(EcdsaSig::from_slice(&secp, &[]), Err(Error::EmptySignature));
+        assert!(EcdsaSig::from_slice(&secp, &data[1..]).is_err());
+    }
+
+    #[test]
+    fn ecdsa_sig_normalize_s() {
+        let secp = Secp256k1::without_caps();
+        let low = hex_bytes("3045022100cb2c6b346a978ab8c61b18b5e9397755cbd17d6eb2fe0083ef32e067fa6c785a02206ce44e613f31d9a6b0517e46f3db1576e9812cc98d159bfdaf759a5014081b5c01").unwrap();
+        let high = hex_bytes("3046022100cb2c6b346a978ab8c61b18b5e9397755cbd17d6eb2fe0083ef32e067fa6c785a022100931bb19ec0ce26594fae81b90c24ea87d12db01d2233043e105cc43cbc2e25e501").unwrap();
+
+        let mut sig = EcdsaSig::from_slice(&secp, &high).unwrap();
+        assert_eq!(sig.to_vec(&secp), high);
+        sig.normalize_s(&secp);
+        assert_eq!(sig.to_vec(&secp), low);
+        sig.normalize_s(&secp);
+        assert_eq!(sig.to_vec(&secp), low);
+    }
+}
@@ -0,0 +1,71 @@
+// Rust Bitcoin Library
+// Written in 2018 by
+//     The Rust Bitcoin developers
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Endianness
+//!
+//! Conversions between integers and little-endian byte arrays
+
+macro_rules! define_le {
+    ($ty:ident, $width:expr, $to_array:ident, $from_array:ident) => (
+        /// Encodes the integer as a little-endian byte array
+        #[inline]
+        pub fn $to_array(val: $ty) -> [u8; $width] {
+            let mut res = [0; $width];
+            for i in 0..$width {
+                res[i] = ((val >> (i * 8)) & 0xff) as u8;
+            }
+            res
+        }
+
+        /// Decodes a little-endian byte array into an integer
+        #[inline]
+        pub fn $from_array(data: &[u8; $width]) -> $ty {
+            let mut res = 0;
+            for i in 0..$width {
+                res |= (data[i] as $ty) << (i * 8);
+            }
+            res
+        }
+    );
+}
+
+define_le!(u16, 2, u16_to_array_le, u16_from_array_le);
+define_le!(u32, 4, u32_to_array_le, u32_from_array_le);
+define_le!(u64, 8, u64_to_array_le, u64_from_array_le);
+define_le!(i64, 8, i64_to_array_le, i64_from_array_le);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn endian_round_trip() {
+        assert_eq!(u16_to_array_le(0x0102), [2, 1]);
+        assert_eq!(u32_to_array_le(0x01020304), [4, 3, 2, 1]);
+        assert_eq!(u64_to_array_le(0x0102030405060708), [8, 7, 6, 5, 4, 3, 2, 1]);
+        assert_eq!(i64_to_array_le(-2), [0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+
+        for &n in &[0, 1, 0xff, 0x100, u16::max_value()] {
+            assert_eq!(u16_from_array_le(&u16_to_array_le(n)), n);
+        }
+        for &n in &[0, 1, 0xffff, 0x10000, u32::max_value()] {
+            assert_eq!(u32_from_array_le(&u32_to_array_le(n)), n);
+        }
+        for &n in &[0, 1, 0xffffffff, 0x100000000, u64::max_value()] {
+            assert_eq!(u64_from_array_le(&u64_to_array_le(n)), n);
+        }
+        for &n in &[0, 1, -1, i64::min_value(), i64::max_value()] {
+            assert_eq!(i64_from_array_le(&i64_to_array_le(n)), n);
+        }
+    }
+}
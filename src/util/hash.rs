@@ -22,6 +22,7 @@ use std::error;
 use std::fmt;
 use std::io::Cursor;
 use std::mem;
+use std::str::FromStr;
 use serde;
 
 use byteorder::{LittleEndian, WriteBytesExt};
@@ -389,6 +390,14 @@ impl fmt::Display for Sha256dHash {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { fmt::LowerHex::fmt(self, f) }
 }
 
+impl FromStr for Sha256dHash {
+    type Err = HexError;
+
+    /// Parse the reversed hex form output by `Display`, as shown by Bitcoin Core
+    /// and block explorers
+    fn from_str(s: &str) -> Result<Sha256dHash, HexError> { Sha256dHash::from_hex(s) }
+}
+
 impl fmt::LowerHex for Sha256dHash {
     /// Output the sha256d hash in reverse, copying Bitcoin Core's behaviour
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -461,6 +470,18 @@ mod tests {
     use util::uint::{Uint128, Uint256};
     use super::*;
 
+    #[test]
+    fn test_sha256d_from_str() {
+        // the genesis coinbase txid, as displayed by explorers
+        let s = "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b";
+        let hash = Sha256dHash::from_str(s).unwrap();
+        assert_eq!(hash[0], 0x3b);
+        assert_eq!(hash[31], 0x4a);
+        assert_eq!(hash.to_string(), s);
+        assert_eq!(hash.be_hex_string(), s);
+        assert_eq!(Sha256dHash::from_str("4a5e"), Err(HexError::BadLength(4)));
+    }
+
     #[test]
     fn test_sha256d() {
         // nb the 5df6... output is the one you get from sha256sum. this is the
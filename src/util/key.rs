@@ -0,0 +1,336 @@
+// Rust Bitcoin Library
+// Written in 2018 by
+//     The Rust Bitcoin developers
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Public keys
+//!  A public key together with the serialization (compressed or not) it is used with
+//!
+
+use std::fmt;
+use std::str::FromStr;
+
+use secp256k1::{self, Secp256k1};
+use serde;
+use serialize::hex::FromHex;
+
+use network::constants::Network;
+use util::Error;
+use util::address::{Address, AddressSet};
+use util::taproot::{TapBranchHash, TapTweakHash};
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+/// A Bitcoin ECDSA public key
+pub struct PublicKey {
+    /// Whether this public key should be serialized as compressed
+    pub compressed: bool,
+    /// The actual ECDSA key
+    pub key: secp256k1::key::PublicKey
+}
+
+impl PublicKey {
+    /// Parses a public key from its 33-byte compressed or 65-byte uncompressed encoding
+    pub fn from_slice(secp: &Secp256k1, data: &[u8]) -> Result<PublicKey, Error> {
+        let compressed = match data.len() {
+            33 => true,
+            65 => false,
+            _ => return Err(Error::Secp256k1(secp256k1::Error::InvalidPublicKey))
+        };
+
+        Ok(PublicKey {
+            compressed: compressed,
+            key: try!(secp256k1::key::PublicKey::from_slice(secp, data))
+        })
+    }
+
+    /// Serializes the key, honoring the `compressed` flag
+    pub fn to_bytes(&self) -> Vec<u8> {
+        if self.compressed {
+            self.key.serialize().to_vec()
+        } else {
+            self.key.serialize_uncompressed().to_vec()
+        }
+    }
+
+    /// All the addresses this key can be paid to on `network`. Segwit forms are
+    /// only given for compressed keys, as outputs paying to uncompressed keys are
+    /// unspendable under the segwit standardness rules.
+    pub fn addresses(&self, network: Network) -> AddressSet {
+        if self.compressed {
+            AddressSet {
+                p2pkh: Address::p2pkh(&self.key, network),
+                p2wpkh: Some(Address::p2wpkh(&self.key, network)),
+                p2sh_p2wpkh: Some(Address::p2shwpkh(&self.key, network)),
+            }
+        } else {
+            AddressSet {
+                p2pkh: Address::p2upkh(&self.key, network),
+                p2wpkh: None,
+                p2sh_p2wpkh: None,
+            }
+        }
+    }
+}
+
+/// A 32-byte x-only public key, as used by BIP340 and taproot. The point is
+/// the one with this x coordinate and an even y coordinate.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct XOnlyPublicKey([u8; 32]);
+
+impl XOnlyPublicKey {
+    /// Parses an x-only key from 32 bytes, checking that it is the x coordinate
+    /// of a point on the curve
+    pub fn from_slice(secp: &Secp256k1, data: &[u8]) -> Result<XOnlyPublicKey, Error> {
+        if data.len() != 32 {
+            return Err(Error::Secp256k1(secp256k1::Error::InvalidPublicKey));
+        }
+        let mut full = [2; 33];
+        full[1..].copy_from_slice(data);
+        try!(secp256k1::key::PublicKey::from_slice(secp, &full));
+
+        let mut ret = [0; 32];
+        ret.copy_from_slice(data);
+        Ok(XOnlyPublicKey(ret))
+    }
+
+    /// Drops the parity of a full public key, returning the x-only key and
+    /// whether the dropped y coordinate was odd
+    pub fn from_public_key(pk: &secp256k1::key::PublicKey) -> (XOnlyPublicKey, bool) {
+        let full = pk.serialize();
+        let mut ret = [0; 32];
+        ret.copy_from_slice(&full[1..]);
+        (XOnlyPublicKey(ret), full[0] == 3)
+    }
+
+    /// The 32-byte serialization of the key
+    pub fn serialize(&self) -> [u8; 32] {
+        self.0
+    }
+
+    /// The full public key with this x coordinate and an even y coordinate
+    pub fn to_public_key(&self, secp: &Secp256k1) -> secp256k1::key::PublicKey {
+        let mut full = [2; 33];
+        full[1..].copy_from_slice(&self.0);
+        // unwrap is safe as the x coordinate was checked on construction
+        secp256k1::key::PublicKey::from_slice(secp, &full).unwrap()
+    }
+}
+
+/// A taproot output key: an x-only key which has been tweaked with the
+/// commitment to its internal key and script tree, see `XOnlyPublicKey::tap_tweak`
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct TweakedPublicKey(XOnlyPublicKey);
+
+impl TweakedPublicKey {
+    /// Wraps a key which is already known to be tweaked
+    pub fn dangerous_assume_tweaked(key: XOnlyPublicKey) -> TweakedPublicKey {
+        TweakedPublicKey(key)
+    }
+
+    /// The tweaked x-only key
+    pub fn as_inner(&self) -> &XOnlyPublicKey {
+        &self.0
+    }
+}
+
+impl XOnlyPublicKey {
+    /// Computes the BIP341 taproot output key `Q = P + H_TapTweak(P || merkle_root)G`
+    /// for this internal key `P`, returning it along with the parity of `Q`'s
+    /// y coordinate. `secp` must be capable of verification.
+    pub fn tap_tweak(&self, secp: &Secp256k1, merkle_root: Option<TapBranchHash>) -> Result<(TweakedPublicKey, bool), Error> {
+        let tweak = TapTweakHash::from_key_and_tweak(self, merkle_root);
+        let tweak = try!(secp256k1::key::SecretKey::from_slice(secp, &tweak[..]));
+        let mut output_key = self.to_public_key(secp);
+        try!(output_key.add_exp_assign(secp, &tweak));
+
+        let (xonly, parity) = XOnlyPublicKey::from_public_key(&output_key);
+        Ok((TweakedPublicKey(xonly), parity))
+    }
+}
+
+impl fmt::Display for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for ch in self.to_bytes() {
+            try!(write!(f, "{:02x}", ch));
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for PublicKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<PublicKey, Error> {
+        let data = try!(s.from_hex().map_err(|_| Error::ParseFailed));
+        PublicKey::from_slice(&Secp256k1::without_caps(), &data)
+    }
+}
+
+// User-facing serialization, as a hex string
+impl serde::Serialize for PublicKey {
+    fn serialize<S>(&self, s: &mut S) -> Result<(), S::Error>
+        where S: serde::Serializer,
+    {
+        s.visit_str(&self.to_string())
+    }
+}
+
+impl serde::Deserialize for PublicKey {
+    fn deserialize<D>(d: &mut D) -> Result<PublicKey, D::Error>
+        where D: serde::Deserializer
+    {
+        struct PublicKeyVisitor;
+        impl serde::de::Visitor for PublicKeyVisitor {
+            type Value = PublicKey;
+
+            fn visit_string<E>(&mut self, v: String) -> Result<PublicKey, E>
+                where E: serde::de::Error
+            {
+                self.visit_str(&v)
+            }
+
+            fn visit_str<E>(&mut self, s: &str) -> Result<PublicKey, E>
+                where E: serde::de::Error
+            {
+                PublicKey::from_str(s).map_err(|_| serde::de::Error::syntax("bad public key"))
+            }
+        }
+
+        d.visit(PublicKeyVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use secp256k1;
+
+    use util::Error;
+    use util::taproot::TapBranchHash;
+    use super::{PublicKey, TweakedPublicKey, XOnlyPublicKey};
+
+    #[test]
+    fn test_pubkey_hex_round_trip() {
+        let s = "03df154ebfcf29d29cc10d5c2565018bce2d9edbab267c31d2caf44a63056cf99f";
+        let key = PublicKey::from_str(s).unwrap();
+        assert!(key.compressed);
+        assert_eq!(key.to_string(), s);
+
+        let s = "048d5141948c1702e8c95f438815794b87f706a8d4cd2bffad1dc1570971032c9b6042a0431ded2478b5c9cf2d81c124a5e57347a3c63ef0e7716cf54d613ba183";
+        let key = PublicKey::from_str(s).unwrap();
+        assert!(!key.compressed);
+        assert_eq!(key.to_string(), s);
+        assert_eq!(PublicKey::from_str(&key.to_string()).unwrap(), key);
+    }
+
+    #[test]
+    fn test_pubkey_invalid() {
+        // not hex
+        match PublicKey::from_str("03df154ebfcf29d29cc10d5c2565018bce2d9edbab267c31d2caf44a63056cf9zz") {
+            Err(Error::ParseFailed) => {},
+            x => panic!("unexpected result {:?}", x),
+        }
+        // wrong length
+        match PublicKey::from_str("03df154ebfcf29d29cc10d5c2565018bce2d9edbab267c31d2caf44a63056cf9") {
+            Err(Error::Secp256k1(secp256k1::Error::InvalidPublicKey)) => {},
+            x => panic!("unexpected result {:?}", x),
+        }
+        // x coordinate not on the curve
+        match PublicKey::from_str("020000000000000000000000000000000000000000000000000000000000000000") {
+            Err(Error::Secp256k1(secp256k1::Error::InvalidPublicKey)) => {},
+            x => panic!("unexpected result {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_pubkey_addresses() {
+        use network::constants::Network;
+        use util::address::Address;
+
+        // BIP49 test vector
+        let key = PublicKey::from_str("03a1af804ac108a8a51782198c2d034b28bf90c8803f5a53f76276fa69a4eae77f").unwrap();
+        let addrs = key.addresses(Network::Testnet);
+        assert_eq!(addrs.p2pkh, Address::p2pkh(&key.key, Network::Testnet));
+        assert_eq!(addrs.p2wpkh.unwrap(), Address::p2wpkh(&key.key, Network::Testnet));
+        assert_eq!(addrs.p2sh_p2wpkh.unwrap().to_string(), "2Mww8dCYPUpKHofjgcXcBCEGmniw9CoaiD2");
+
+        let key = PublicKey::from_str("048d5141948c1702e8c95f438815794b87f706a8d4cd2bffad1dc1570971032c9b6042a0431ded2478b5c9cf2d81c124a5e57347a3c63ef0e7716cf54d613ba183").unwrap();
+        let addrs = key.addresses(Network::Bitcoin);
+        assert_eq!(addrs.p2pkh.to_string(), "1QJVDzdqb1VpbDK7uDeyVXy9mR27CJiyhY");
+        assert_eq!(addrs.p2wpkh, None);
+        assert_eq!(addrs.p2sh_p2wpkh, None);
+    }
+
+    #[test]
+    fn test_xonly_pubkey() {
+        use secp256k1::Secp256k1;
+        use serialize::hex::FromHex;
+
+        let secp = Secp256k1::without_caps();
+
+        // odd y coordinate: parity is reported and lost on the way back
+        let odd = PublicKey::from_str("03df154ebfcf29d29cc10d5c2565018bce2d9edbab267c31d2caf44a63056cf99f").unwrap();
+        let (xonly, parity) = XOnlyPublicKey::from_public_key(&odd.key);
+        assert!(parity);
+        assert_eq!(&xonly.serialize()[..], &"df154ebfcf29d29cc10d5c2565018bce2d9edbab267c31d2caf44a63056cf99f".from_hex().unwrap()[..]);
+        assert_eq!(&xonly.to_public_key(&secp).serialize()[..],
+                   &"02df154ebfcf29d29cc10d5c2565018bce2d9edbab267c31d2caf44a63056cf99f".from_hex().unwrap()[..]);
+
+        // the even-parity key round-trips exactly
+        let even = xonly.to_public_key(&secp);
+        assert_eq!(XOnlyPublicKey::from_public_key(&even), (xonly, false));
+        assert_eq!(XOnlyPublicKey::from_slice(&secp, &xonly.serialize()).unwrap(), xonly);
+
+        assert!(XOnlyPublicKey::from_slice(&secp, &[0; 32]).is_err());
+        assert!(XOnlyPublicKey::from_slice(&secp, &[1; 31]).is_err());
+    }
+
+    #[test]
+    fn test_tap_tweak() {
+        use secp256k1::Secp256k1;
+        use serialize::hex::FromHex;
+
+        let secp = Secp256k1::new();
+        // BIP341 wallet test vectors, scriptPubKey cases 0 and 1
+        let internal = XOnlyPublicKey::from_slice(&secp, &"d6889cb081036e0faefa3a35157ad71086b123b2b144b649798b494c300a961d".from_hex().unwrap()).unwrap();
+        let (tweaked, _) = internal.tap_tweak(&secp, None).unwrap();
+        assert_eq!(&tweaked.as_inner().serialize()[..],
+                   &"53a1f6e454df1aa2776a2814a721372d6258050de330b3c6d10ee8f4e0dda343".from_hex().unwrap()[..]);
+
+        let internal = XOnlyPublicKey::from_slice(&secp, &"187791b6f712a8ea41c8ecdd0ee77fab3e85263b37e1ec18a3651926b3a6cf27".from_hex().unwrap()).unwrap();
+        let root = TapBranchHash::from(&"5b75adecf53548f3ec6ad7d78383bf84cc57b55a3127c72b9a2481752dd88b21".from_hex().unwrap()[..]);
+        let (tweaked, _) = internal.tap_tweak(&secp, Some(root)).unwrap();
+        assert_eq!(&tweaked.as_inner().serialize()[..],
+                   &"147c9c57132f6e7ecddba9800bb0c4449251c92a1e60371ee77557b6620f3ea3".from_hex().unwrap()[..]);
+        assert_eq!(TweakedPublicKey::dangerous_assume_tweaked(*tweaked.as_inner()), tweaked);
+
+        // a context without verification capability cannot tweak
+        assert!(internal.tap_tweak(&Secp256k1::without_caps(), None).is_err());
+    }
+
+    #[test]
+    fn test_serde() {
+        use strason::Json;
+
+        for s in &["0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+                   "0479be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798483ada7726a3c4655da4fbfc0e1108a8fd17b448a68554199c47d08ffb10d4b8"] {
+            let pk = PublicKey::from_str(s).unwrap();
+            let json = Json::from_serialize(&pk).unwrap();
+            assert_eq!(json.string(), Some(*s));
+            let des: PublicKey = json.into_deserialize().unwrap();
+            assert_eq!(des, pk);
+        }
+
+        let json = Json::from_serialize(&"02").unwrap();
+        assert!(json.into_deserialize::<PublicKey>().is_err());
+    }
+}
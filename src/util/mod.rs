@@ -17,15 +17,21 @@
 //! Functions needed by all parts of the Bitcoin library
 
 pub mod privkey;
+pub mod key;
 pub mod address;
 pub mod base58;
 pub mod bip32;
 pub mod bip143;
+pub mod bip158;
 pub mod contracthash;
+pub mod core_json;
 pub mod decimal;
+pub mod ecdsa;
+pub mod endian;
 pub mod hash;
 pub mod iter;
 pub mod misc;
+pub mod taproot;
 pub mod uint;
 
 #[cfg(feature = "fuzztarget")]
@@ -66,6 +72,8 @@ pub enum Error {
     Base58(base58::Error),
     /// Bech32 encoding error
     Bech32(bitcoin_bech32::Error),
+    /// BIP32 key derivation error
+    Bip32(bip32::Error),
     /// Error from the `byteorder` crate
     ByteOrder(io::Error),
     /// Network magic was not what we expected
@@ -89,7 +97,15 @@ pub enum Error {
     /// Error propagated from subsystem
     Detail(String, Box<Error>),
     /// Unsupported witness version
-    UnsupportedWitnessVersion(u8)
+    UnsupportedWitnessVersion(u8),
+    /// Sum of output values exceeds the money supply
+    ValueOverflow,
+    /// An uncompressed public key was used where only compressed keys are allowed
+    UncompressedPubkey,
+    /// The sighash type is not supported by the signature hashing algorithm
+    UnsupportedSigHashType(u32),
+    /// The transaction has no input at the given index
+    InputIndexOutOfRange(usize)
 }
 
 impl fmt::Display for Error {
@@ -98,6 +114,7 @@ impl fmt::Display for Error {
             Error::Io(ref e) => fmt::Display::fmt(e, f),
             Error::Base58(ref e) => fmt::Display::fmt(e, f),
             Error::Bech32(ref e) => fmt::Display::fmt(e, f),
+            Error::Bip32(ref e) => fmt::Display::fmt(e, f),
             Error::ByteOrder(ref e) => fmt::Display::fmt(e, f),
             Error::BadNetworkMagic(exp, got) => write!(f, "expected network magic 0x{:x}, got 0x{:x}", exp, got),
             Error::BadNetworkMessage(ref got) => write!(f, "incorrect network message {}", got),
@@ -114,6 +131,7 @@ impl error::Error for Error {
             Error::Io(ref e) => Some(e),
             Error::Base58(ref e) => Some(e),
             Error::Bech32(ref e) => Some(e),
+            Error::Bip32(ref e) => Some(e),
             Error::ByteOrder(ref e) => Some(e),
             Error::Detail(_, ref e) => Some(e),
             Error::Secp256k1(ref e) => Some(e),
@@ -126,6 +144,7 @@ impl error::Error for Error {
             Error::Io(ref e) => e.description(),
             Error::Base58(ref e) => e.description(),
             Error::Bech32(ref e) => e.description(),
+            Error::Bip32(ref e) => e.description(),
             Error::ByteOrder(ref e) => e.description(),
             Error::BadNetworkMagic(_, _) => "incorrect network magic",
             Error::BadNetworkMessage(_) => "incorrect/unexpected network message",
@@ -137,7 +156,11 @@ impl error::Error for Error {
             Error::SpvBadTarget => "target incorrect",
             Error::SpvBadProofOfWork => "target correct but not attained",
             Error::Detail(_, ref e) => e.description(),
-            Error::UnsupportedWitnessVersion(_) => "unsupported witness version"
+            Error::UnsupportedWitnessVersion(_) => "unsupported witness version",
+            Error::ValueOverflow => "value exceeds money supply",
+            Error::UncompressedPubkey => "uncompressed public key not allowed",
+            Error::UnsupportedSigHashType(_) => "unsupported sighash type",
+            Error::InputIndexOutOfRange(_) => "input index out of range"
         }
     }
 }
@@ -153,6 +176,12 @@ impl From<base58::Error> for Error {
     }
 }
 
+impl From<bip32::Error> for Error {
+    fn from(e: bip32::Error) -> Error {
+        Error::Bip32(e)
+    }
+}
+
 impl From<bitcoin_bech32::Error> for Error {
     fn from(e: bitcoin_bech32::Error) -> Error {
         Error::Bech32(e)
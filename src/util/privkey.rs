@@ -15,8 +15,11 @@
 //!  A private key represents the secret data associated with its proposed use
 //!
 use std::str::FromStr;
+use byteorder::{ByteOrder, LittleEndian};
+#[cfg(feature = "privkey-serde")]
+use serde;
 use util::Error;
-use secp256k1::Secp256k1;
+use secp256k1::{self, ffi, Message, Secp256k1, Signature};
 use secp256k1::key::{PublicKey, SecretKey};
 use util::address::Address;
 use network::constants::Network;
@@ -66,6 +69,47 @@ impl Privkey {
         }
     }
 
+    /// Signs `msg` using an RFC6979 deterministic nonce, libsecp256k1's
+    /// default, so signing the same message twice gives the same signature.
+    /// Requires a signing-capable context.
+    pub fn sign_ecdsa(&self, secp: &Secp256k1, msg: &Message) -> Result<Signature, Error> {
+        Ok(try!(secp.sign(msg, &self.key)))
+    }
+
+    /// Signs `msg` as `sign_ecdsa` does, but with `noncedata` mixed into the
+    /// RFC6979 nonce derivation as extra entropy. The result is still
+    /// deterministic for a given `noncedata`.
+    ///
+    /// The secp256k1 bindings do not expose extra entropy on `Secp256k1`, so
+    /// this signs through a signing context of its own, created once per
+    /// thread and reused.
+    pub fn sign_ecdsa_with_noncedata(&self, msg: &Message, noncedata: &[u8; 32]) -> Result<Signature, Error> {
+        SIGNING_CONTEXT.with(|signer| signer.sign(msg, &self.key, noncedata))
+    }
+
+    /// Signs `msg` as bitcoin-core does: the nonce is ground, by passing an
+    /// increasing counter as extra nonce data, until R has its high bit clear.
+    /// The DER encoding then has a 32-byte R and is at most 71 bytes. S is always
+    /// low. Requires a signing-capable context.
+    pub fn sign_ecdsa_low_r(&self, secp: &Secp256k1, msg: &Message) -> Result<Signature, Error> {
+        let sig = try!(self.sign_ecdsa(secp, msg));
+        if is_low_r(secp, &sig) {
+            return Ok(sig);
+        }
+        SIGNING_CONTEXT.with(|signer| {
+            let mut counter = 0u32;
+            let mut noncedata = [0; 32];
+            loop {
+                counter += 1;
+                LittleEndian::write_u32(&mut noncedata[..4], counter);
+                let sig = try!(signer.sign(msg, &self.key, &noncedata));
+                if is_low_r(secp, &sig) {
+                    return Ok(sig);
+                }
+            }
+        })
+    }
+
     /// Accessor for the underlying secp key
     #[inline]
     pub fn secret_key(&self) -> &SecretKey {
@@ -91,13 +135,51 @@ impl Privkey {
     }
 }
 
+/// Whether the R value of `sig` has its high bit clear, so that its DER
+/// encoding needs no padding byte
+fn is_low_r(secp: &Secp256k1, sig: &Signature) -> bool {
+    sig.serialize_compact(secp)[0] < 0x80
+}
+
+thread_local!(static SIGNING_CONTEXT: SigningContext = SigningContext::new());
+
+/// A raw libsecp256k1 signing context, used for signing with extra nonce data.
+/// The pointer is null if the context could not be created.
+struct SigningContext(*mut ffi::Context);
+
+impl SigningContext {
+    fn new() -> SigningContext {
+        SigningContext(unsafe { ffi::secp256k1_context_create(ffi::SECP256K1_START_SIGN) })
+    }
+
+    fn sign(&self, msg: &Message, sk: &SecretKey, noncedata: &[u8; 32]) -> Result<Signature, Error> {
+        if self.0.is_null() {
+            return Err(Error::Secp256k1(secp256k1::Error::IncapableContext));
+        }
+        unsafe {
+            let mut ret = ffi::Signature::blank();
+            if ffi::secp256k1_ecdsa_sign(self.0, &mut ret, msg.as_ptr(), sk.as_ptr(),
+                                         ffi::secp256k1_nonce_function_rfc6979,
+                                         noncedata.as_ptr() as *const _) != 1 {
+                return Err(Error::Secp256k1(secp256k1::Error::InvalidSecretKey));
+            }
+            Ok(Signature::from(ret))
+        }
+    }
+}
+
+impl Drop for SigningContext {
+    fn drop(&mut self) {
+        if !self.0.is_null() {
+            unsafe { ffi::secp256k1_context_destroy(self.0); }
+        }
+    }
+}
+
 impl ToString for Privkey {
     fn to_string(&self) -> String {
         let mut ret = [0; 34];
-        ret[0] = match self.network {
-            Network::Bitcoin => 128,
-            Network::Testnet => 239
-        };
+        ret[0] = if self.network.is_testnet_like() { 239 } else { 128 };
         ret[1..33].copy_from_slice(&self.key[..]);
         if self.compressed {
             ret[33] = 1;
@@ -116,7 +198,8 @@ impl FromStr for Privkey {
 
         let compressed = match data.len() {
             33 => false,
-            34 => true,
+            34 if data[33] == 1 => true,
+            34 => { return Err(Error::Base58(base58::Error::InvalidCompressionFlag(data[33]))); }
             _ => { return Err(Error::Base58(base58::Error::InvalidLength(data.len()))); }
         };
 
@@ -138,6 +221,44 @@ impl FromStr for Privkey {
     }
 }
 
+// User-facing serialization, only ever as WIF. Behind a feature so that
+// applications can make sure keys never end up in serialized data.
+#[cfg(feature = "privkey-serde")]
+impl serde::Serialize for Privkey {
+    fn serialize<S>(&self, s: &mut S) -> Result<(), S::Error>
+        where S: serde::Serializer,
+    {
+        s.visit_str(&self.to_string())
+    }
+}
+
+#[cfg(feature = "privkey-serde")]
+impl serde::Deserialize for Privkey {
+    fn deserialize<D>(d: &mut D) -> Result<Privkey, D::Error>
+        where D: serde::Deserializer
+    {
+        struct PrivkeyVisitor;
+        impl serde::de::Visitor for PrivkeyVisitor {
+            type Value = Privkey;
+
+            fn visit_string<E>(&mut self, v: String) -> Result<Privkey, E>
+                where E: serde::de::Error
+            {
+                self.visit_str(&v)
+            }
+
+            fn visit_str<E>(&mut self, s: &str) -> Result<Privkey, E>
+                where E: serde::de::Error
+            {
+                // don't echo the input, it may be most of a valid key
+                Privkey::from_str(s).map_err(|_| serde::de::Error::syntax("bad WIF private key"))
+            }
+        }
+
+        d.visit(PrivkeyVisitor)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::Privkey;
@@ -168,4 +289,99 @@ mod tests {
         let pk = sk.to_legacy_address(&secp).unwrap();
         assert_eq!(&pk.to_string(), "1GhQvF6dL8xa6wBxLnWmHcQsurx9RxiMc8");
     }
+
+    #[test]
+    fn test_compression_flag() {
+        use util::{base58, Error};
+
+        let mut data = [0x80; 34];
+        data[33] = 1;
+        let sk = Privkey::from_str(&base58::check_encode_slice(&data)).unwrap();
+        assert!(sk.is_compressed());
+        assert_eq!(sk.to_string(), base58::check_encode_slice(&data));
+
+        let sk = Privkey::from_str(&base58::check_encode_slice(&data[..33])).unwrap();
+        assert!(!sk.is_compressed());
+        assert_eq!(sk.to_string(), base58::check_encode_slice(&data[..33]));
+
+        // anything other than 0x01 after the key is malformed
+        for &flag in &[0, 2, 0x80] {
+            data[33] = flag;
+            match Privkey::from_str(&base58::check_encode_slice(&data)) {
+                Err(Error::Base58(base58::Error::InvalidCompressionFlag(f))) => assert_eq!(f, flag),
+                x => panic!("unexpected result for flag {}: {:?}", flag, x.map(|sk| sk.to_string())),
+            }
+        }
+        match Privkey::from_str(&base58::check_encode_slice(&[0x80; 35])) {
+            Err(Error::Base58(base58::Error::InvalidLength(35))) => {},
+            x => panic!("unexpected result {:?}", x.map(|sk| sk.to_string())),
+        }
+    }
+
+    #[test]
+    fn test_sign_ecdsa() {
+        use secp256k1::Message;
+
+        let secp = Secp256k1::new();
+        let sk = Privkey::from_str("cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy").unwrap();
+        let pk = sk.public_key(&secp).unwrap();
+        let msg = Message::from_slice(&[0xab; 32]).unwrap();
+
+        let sig = sk.sign_ecdsa(&secp, &msg).unwrap();
+        assert_eq!(sk.sign_ecdsa(&secp, &msg).unwrap(), sig);
+        assert!(secp.verify(&msg, &sig, &pk).is_ok());
+
+        let sig_a = sk.sign_ecdsa_with_noncedata(&msg, &[1; 32]).unwrap();
+        assert_eq!(sk.sign_ecdsa_with_noncedata(&msg, &[1; 32]).unwrap(), sig_a);
+        assert!(secp.verify(&msg, &sig_a, &pk).is_ok());
+        let sig_b = sk.sign_ecdsa_with_noncedata(&msg, &[2; 32]).unwrap();
+        assert!(secp.verify(&msg, &sig_b, &pk).is_ok());
+        assert!(sig_a != sig_b);
+        assert!(sig_a != sig);
+
+        let verify_only = Secp256k1::without_caps();
+        assert!(sk.sign_ecdsa(&verify_only, &msg).is_err());
+    }
+
+    #[test]
+    fn test_sign_ecdsa_low_r() {
+        use secp256k1::Message;
+
+        let secp = Secp256k1::new();
+        let sk = Privkey::from_str("cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy").unwrap();
+        let pk = sk.public_key(&secp).unwrap();
+
+        // about half of these need grinding
+        for i in 0..32 {
+            let msg = Message::from_slice(&[i; 32]).unwrap();
+            let sig = sk.sign_ecdsa_low_r(&secp, &msg).unwrap();
+            assert!(secp.verify(&msg, &sig, &pk).is_ok());
+            assert!(sig.serialize_der(&secp).len() <= 71);
+            assert!(sig.serialize_compact(&secp)[0] < 0x80);
+
+            let mut normalized = sig;
+            normalized.normalize_s(&secp);
+            assert_eq!(normalized, sig);
+
+            assert_eq!(sk.sign_ecdsa_low_r(&secp, &msg).unwrap(), sig);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "privkey-serde")]
+    fn test_serde() {
+        use strason::Json;
+
+        for wif in &["cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy",
+                     "5JYkZjmN7PVMjJUfJWfRFwtuXTGB439XV6faajeHPAM9Z2PT2R3"] {
+            let sk = Privkey::from_str(wif).unwrap();
+            let json = Json::from_serialize(&sk).unwrap();
+            assert_eq!(json.string(), Some(*wif));
+            let des: Privkey = json.into_deserialize().unwrap();
+            assert!(des == sk);
+        }
+
+        let json = Json::from_serialize(&"cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpz").unwrap();
+        assert!(json.into_deserialize::<Privkey>().is_err());
+    }
 }
@@ -0,0 +1,594 @@
+// Rust Bitcoin Library
+// Written in 2018 by
+//     The Rust Bitcoin developers
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Taproot
+//!
+//! BIP341 script trees: the tagged hashes committing to them,
+//! `SHA256(SHA256(tag) || SHA256(tag) || msg)`, a builder for assembling
+//! them and the control blocks proving a leaf's membership.
+//!
+
+use std::collections::HashMap;
+use std::{error, fmt};
+
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use secp256k1::{self, Secp256k1};
+
+use blockdata::script::Script;
+use network::encodable::VarInt;
+use network::serialize::serialize;
+use util;
+use util::key::{TweakedPublicKey, XOnlyPublicKey};
+
+/// SHA256("TapLeaf")
+const TAPLEAF_TAG: [u8; 32] = [
+    0xae, 0xea, 0x8f, 0xdc, 0x42, 0x08, 0x98, 0x31, 0x05, 0x73, 0x4b, 0x58, 0x08, 0x1d, 0x1e, 0x26,
+    0x38, 0xd3, 0x5f, 0x1c, 0xb5, 0x40, 0x08, 0xd4, 0xd3, 0x57, 0xca, 0x03, 0xbe, 0x78, 0xe9, 0xee,
+];
+/// SHA256("TapBranch")
+const TAPBRANCH_TAG: [u8; 32] = [
+    0x19, 0x41, 0xa1, 0xf2, 0xe5, 0x6e, 0xb9, 0x5f, 0xa2, 0xa9, 0xf1, 0x94, 0xbe, 0x5c, 0x01, 0xf7,
+    0x21, 0x6f, 0x33, 0xed, 0x82, 0xb0, 0x91, 0x46, 0x34, 0x90, 0xd0, 0x5b, 0xf5, 0x16, 0xa0, 0x15,
+];
+/// SHA256("TapTweak")
+const TAPTWEAK_TAG: [u8; 32] = [
+    0xe8, 0x0f, 0xe1, 0x63, 0x9c, 0x9c, 0xa0, 0x50, 0xe3, 0xaf, 0x1b, 0x39, 0xc1, 0x43, 0xc6, 0x3e,
+    0x42, 0x9c, 0xbc, 0xeb, 0x15, 0xd9, 0x40, 0xfb, 0xb5, 0xc5, 0xa1, 0xf4, 0xaf, 0x57, 0xc5, 0xe9,
+];
+
+/// The leaf version of BIP342 tapscript
+pub const TAPROOT_LEAF_TAPSCRIPT: u8 = 0xc0;
+/// The maximum depth of a script tree, i.e. the maximum length of a Merkle path
+pub const TAPROOT_CONTROL_MAX_NODE_COUNT: usize = 128;
+/// The size of a control block without any Merkle path
+pub const TAPROOT_CONTROL_BASE_SIZE: usize = 33;
+/// The size of each node of a control block's Merkle path
+pub const TAPROOT_CONTROL_NODE_SIZE: usize = 32;
+
+/// Hashes the concatenation of `data` under the tag whose SHA256 is `tag`
+fn tagged_hash(tag: &[u8; 32], data: &[&[u8]]) -> [u8; 32] {
+    let mut engine = Sha256::new();
+    engine.input(tag);
+    engine.input(tag);
+    for d in data {
+        engine.input(d);
+    }
+    let mut ret = [0; 32];
+    engine.result(&mut ret);
+    ret
+}
+
+/// The tagged hash of a leaf script and its version
+pub struct TapLeafHash([u8; 32]);
+impl_array_newtype!(TapLeafHash, u8, 32);
+impl_array_newtype_show!(TapLeafHash);
+
+/// The tagged hash of an inner node of a script tree; the hash of the root
+/// node is the tree's Merkle root
+pub struct TapBranchHash([u8; 32]);
+impl_array_newtype!(TapBranchHash, u8, 32);
+impl_array_newtype_show!(TapBranchHash);
+
+/// The tagged hash committing an internal key to a script tree, used to
+/// tweak the internal key into the output key
+pub struct TapTweakHash([u8; 32]);
+impl_array_newtype!(TapTweakHash, u8, 32);
+impl_array_newtype_show!(TapTweakHash);
+
+impl TapLeafHash {
+    /// Computes the leaf hash of `script` with the given leaf version
+    pub fn from_script(script: &Script, leaf_version: u8) -> TapLeafHash {
+        // unwrap is safe as serializing into a vector cannot fail
+        let len = serialize(&VarInt(script.len() as u64)).unwrap();
+        TapLeafHash(tagged_hash(&TAPLEAF_TAG, &[&[leaf_version], &len, &script[..]]))
+    }
+}
+
+impl TapBranchHash {
+    /// Computes the hash of a node from the hashes of its two children, which
+    /// may be leaf or branch hashes. The order of the children does not matter.
+    pub fn from_node_hashes(a: &[u8], b: &[u8]) -> TapBranchHash {
+        if a < b {
+            TapBranchHash(tagged_hash(&TAPBRANCH_TAG, &[a, b]))
+        } else {
+            TapBranchHash(tagged_hash(&TAPBRANCH_TAG, &[b, a]))
+        }
+    }
+}
+
+impl TapTweakHash {
+    /// Computes the tweak for `internal_key` committing to the script tree
+    /// with the given Merkle root, or to no script tree at all
+    pub fn from_key_and_tweak(internal_key: &XOnlyPublicKey, merkle_root: Option<TapBranchHash>) -> TapTweakHash {
+        let key = internal_key.serialize();
+        match merkle_root {
+            Some(ref root) => TapTweakHash(tagged_hash(&TAPTWEAK_TAG, &[&key, &root[..]])),
+            None => TapTweakHash(tagged_hash(&TAPTWEAK_TAG, &[&key])),
+        }
+    }
+}
+
+/// A taproot error
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// A leaf was added deeper than `TAPROOT_CONTROL_MAX_NODE_COUNT`
+    InvalidMerkleTreeDepth(usize),
+    /// A leaf version with its lowest bit set, which is reserved for the parity bit
+    InvalidLeafVersion(u8),
+    /// A leaf was added at a shallower depth than an incomplete branch
+    /// before it, so leaves were not given in depth-first order
+    NodeNotInDfsOrder,
+    /// A leaf was added after the tree was already complete
+    OverCompleteTree,
+    /// The tree has branches which are missing children
+    IncompleteTree,
+    /// The tree has no leaves
+    EmptyTree,
+    /// A control block whose size is not 33 plus a multiple of 32, up to
+    /// the maximum tree depth
+    InvalidControlBlockSize(usize),
+    /// A secp256k1 error occured while tweaking the internal key
+    Secp256k1(secp256k1::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::InvalidMerkleTreeDepth(d) => write!(f, "merkle tree depth {} exceeds {}", d, TAPROOT_CONTROL_MAX_NODE_COUNT),
+            Error::InvalidLeafVersion(v) => write!(f, "leaf version {:#04x} is invalid", v),
+            Error::InvalidControlBlockSize(n) => write!(f, "control block size {} is invalid", n),
+            Error::Secp256k1(ref e) => fmt::Display::fmt(e, f),
+            _ => f.write_str(error::Error::description(self)),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> {
+        if let Error::Secp256k1(ref e) = *self {
+            Some(e)
+        } else {
+            None
+        }
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            Error::InvalidMerkleTreeDepth(_) => "merkle tree too deep",
+            Error::InvalidLeafVersion(_) => "invalid leaf version",
+            Error::NodeNotInDfsOrder => "leaves not added in depth-first order",
+            Error::OverCompleteTree => "leaf added to a complete tree",
+            Error::IncompleteTree => "tree has branches with missing children",
+            Error::EmptyTree => "tree has no leaves",
+            Error::InvalidControlBlockSize(_) => "invalid control block size",
+            Error::Secp256k1(ref e) => error::Error::description(e),
+        }
+    }
+}
+
+impl From<secp256k1::Error> for Error {
+    fn from(e: secp256k1::Error) -> Error { Error::Secp256k1(e) }
+}
+
+/// Converts an error from `util::key`, which for x-only keys only ever
+/// comes from secp256k1
+fn key_error(e: util::Error) -> Error {
+    match e {
+        util::Error::Secp256k1(e) => Error::Secp256k1(e),
+        _ => unreachable!("x-only key operations only fail in secp256k1"),
+    }
+}
+
+/// A leaf of a script tree along with its path to the root, so far
+#[derive(Clone, Debug)]
+struct LeafInfo {
+    script: Script,
+    leaf_version: u8,
+    merkle_branch: Vec<[u8; 32]>,
+}
+
+/// A node of a script tree along with all the leaves under it
+#[derive(Clone, Debug)]
+struct NodeInfo {
+    hash: [u8; 32],
+    leaves: Vec<LeafInfo>,
+}
+
+impl NodeInfo {
+    fn new_leaf(script: Script, leaf_version: u8) -> NodeInfo {
+        let hash = TapLeafHash::from_script(&script, leaf_version);
+        NodeInfo {
+            hash: hash.0,
+            leaves: vec![LeafInfo {
+                script: script,
+                leaf_version: leaf_version,
+                merkle_branch: vec![],
+            }],
+        }
+    }
+
+    fn combine(a: NodeInfo, b: NodeInfo) -> NodeInfo {
+        let hash = TapBranchHash::from_node_hashes(&a.hash, &b.hash);
+        let mut leaves = Vec::with_capacity(a.leaves.len() + b.leaves.len());
+        for mut leaf in a.leaves {
+            leaf.merkle_branch.push(b.hash);
+            leaves.push(leaf);
+        }
+        for mut leaf in b.leaves {
+            leaf.merkle_branch.push(a.hash);
+            leaves.push(leaf);
+        }
+        NodeInfo { hash: hash.0, leaves: leaves }
+    }
+}
+
+/// Builds a script tree from leaves given with their depths, in depth-first
+/// order. Each leaf is combined with its sibling as soon as both are known.
+#[derive(Clone, Debug, Default)]
+pub struct TaprootBuilder {
+    /// The pending node at each depth which is still waiting for its sibling
+    branch: Vec<Option<NodeInfo>>,
+}
+
+impl TaprootBuilder {
+    /// Creates a builder for an empty tree
+    pub fn new() -> TaprootBuilder { TaprootBuilder::default() }
+
+    /// Adds a tapscript leaf at the given depth, where the root is at depth 0
+    pub fn add_leaf(self, depth: usize, script: Script) -> Result<TaprootBuilder, Error> {
+        self.add_leaf_with_ver(depth, script, TAPROOT_LEAF_TAPSCRIPT)
+    }
+
+    /// Adds a leaf with an explicit leaf version at the given depth
+    pub fn add_leaf_with_ver(self, depth: usize, script: Script, leaf_version: u8) -> Result<TaprootBuilder, Error> {
+        if leaf_version & 1 != 0 {
+            return Err(Error::InvalidLeafVersion(leaf_version));
+        }
+        self.insert(NodeInfo::new_leaf(script, leaf_version), depth)
+    }
+
+    /// Whether the leaves added so far form a complete tree
+    pub fn is_complete(&self) -> bool {
+        self.branch.len() == 1 && self.branch[0].is_some()
+    }
+
+    fn insert(mut self, mut node: NodeInfo, mut depth: usize) -> Result<TaprootBuilder, Error> {
+        if depth > TAPROOT_CONTROL_MAX_NODE_COUNT {
+            return Err(Error::InvalidMerkleTreeDepth(depth));
+        }
+        if self.is_complete() {
+            return Err(Error::OverCompleteTree);
+        }
+        // A pending node deeper than this one can never be completed
+        if self.branch.len() > depth + 1 {
+            return Err(Error::NodeNotInDfsOrder);
+        }
+        // Combine with pending siblings for as long as there are any
+        while self.branch.len() == depth + 1 {
+            let sibling = match self.branch.pop() {
+                Some(Some(sibling)) => sibling,
+                _ => {
+                    self.branch.push(None);
+                    break;
+                }
+            };
+            node = NodeInfo::combine(sibling, node);
+            depth -= 1;
+        }
+        if self.branch.len() < depth + 1 {
+            self.branch.resize(depth + 1, None);
+        }
+        self.branch[depth] = Some(node);
+        Ok(self)
+    }
+
+    /// Finishes the tree and tweaks `internal_key` with its Merkle root.
+    /// `secp` must be capable of verification.
+    pub fn finalize(mut self, secp: &Secp256k1, internal_key: XOnlyPublicKey) -> Result<TaprootSpendInfo, Error> {
+        if self.branch.is_empty() {
+            return Err(Error::EmptyTree);
+        }
+        if !self.is_complete() {
+            return Err(Error::IncompleteTree);
+        }
+        // unwraps are safe as the tree was just checked to be complete
+        let root = self.branch.pop().unwrap().unwrap();
+
+        let mut spend_info = try!(TaprootSpendInfo::new_key_spend(secp, internal_key, Some(TapBranchHash(root.hash))));
+        for leaf in root.leaves {
+            spend_info.script_map.entry((leaf.script, leaf.leaf_version))
+                .or_insert_with(Vec::new)
+                .push(leaf.merkle_branch);
+        }
+        Ok(spend_info)
+    }
+}
+
+/// Everything needed to spend a taproot output, by key or by script
+#[derive(Clone, Debug)]
+pub struct TaprootSpendInfo {
+    internal_key: XOnlyPublicKey,
+    merkle_root: Option<TapBranchHash>,
+    output_key: TweakedPublicKey,
+    output_key_parity: bool,
+    /// The Merkle paths of each leaf, from the leaf's sibling up to the
+    /// root. A script may appear in several leaves and so have several paths.
+    script_map: HashMap<(Script, u8), Vec<Vec<[u8; 32]>>>,
+}
+
+impl TaprootSpendInfo {
+    /// Computes the spend info for an output committing to the given Merkle
+    /// root, whose scripts are not known. `secp` must be capable of verification.
+    pub fn new_key_spend(secp: &Secp256k1, internal_key: XOnlyPublicKey, merkle_root: Option<TapBranchHash>) -> Result<TaprootSpendInfo, Error> {
+        let (output_key, parity) = try!(internal_key.tap_tweak(secp, merkle_root).map_err(key_error));
+        Ok(TaprootSpendInfo {
+            internal_key: internal_key,
+            merkle_root: merkle_root,
+            output_key: output_key,
+            output_key_parity: parity,
+            script_map: HashMap::new(),
+        })
+    }
+
+    /// The untweaked internal key
+    pub fn internal_key(&self) -> XOnlyPublicKey { self.internal_key }
+
+    /// The Merkle root of the script tree, if there is one
+    pub fn merkle_root(&self) -> Option<TapBranchHash> { self.merkle_root }
+
+    /// The output key, which goes in the scriptPubKey
+    pub fn output_key(&self) -> TweakedPublicKey { self.output_key }
+
+    /// Whether the full output key has an odd y coordinate
+    pub fn output_key_parity(&self) -> bool { self.output_key_parity }
+
+    /// The Merkle paths of every leaf, keyed by script and leaf version
+    pub fn script_map(&self) -> &HashMap<(Script, u8), Vec<Vec<[u8; 32]>>> { &self.script_map }
+
+    /// The control block for spending the given leaf, using its shortest
+    /// Merkle path if the script appears more than once
+    pub fn control_block(&self, script_ver: &(Script, u8)) -> Option<ControlBlock> {
+        let branch = match self.script_map.get(script_ver) {
+            Some(branches) => branches.iter().min_by_key(|b| b.len()),
+            None => None,
+        };
+        branch.map(|branch| ControlBlock {
+            leaf_version: script_ver.1,
+            output_key_parity: self.output_key_parity,
+            internal_key: self.internal_key,
+            merkle_branch: branch.clone(),
+        })
+    }
+}
+
+/// The control block of a taproot script-path spend, the last element of
+/// its witness, proving that the spent script is committed to by the output key
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ControlBlock {
+    /// The leaf version of the spent script
+    pub leaf_version: u8,
+    /// Whether the full output key has an odd y coordinate
+    pub output_key_parity: bool,
+    /// The untweaked internal key
+    pub internal_key: XOnlyPublicKey,
+    /// The Merkle path from the leaf's sibling up to the root
+    pub merkle_branch: Vec<[u8; 32]>,
+}
+
+impl ControlBlock {
+    /// Parses a control block, checking its size and that the internal key
+    /// is on the curve
+    pub fn from_slice(secp: &Secp256k1, data: &[u8]) -> Result<ControlBlock, Error> {
+        if data.len() < TAPROOT_CONTROL_BASE_SIZE
+            || (data.len() - TAPROOT_CONTROL_BASE_SIZE) % TAPROOT_CONTROL_NODE_SIZE != 0
+            || (data.len() - TAPROOT_CONTROL_BASE_SIZE) / TAPROOT_CONTROL_NODE_SIZE > TAPROOT_CONTROL_MAX_NODE_COUNT {
+            return Err(Error::InvalidControlBlockSize(data.len()));
+        }
+        let internal_key = try!(XOnlyPublicKey::from_slice(secp, &data[1..TAPROOT_CONTROL_BASE_SIZE]).map_err(key_error));
+        let merkle_branch = data[TAPROOT_CONTROL_BASE_SIZE..].chunks(TAPROOT_CONTROL_NODE_SIZE).map(|node| {
+            let mut hash = [0; 32];
+            hash.copy_from_slice(node);
+            hash
+        }).collect();
+
+        Ok(ControlBlock {
+            leaf_version: data[0] & 0xfe,
+            output_key_parity: data[0] & 1 == 1,
+            internal_key: internal_key,
+            merkle_branch: merkle_branch,
+        })
+    }
+
+    /// Serializes the control block
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut ret = Vec::with_capacity(TAPROOT_CONTROL_BASE_SIZE + TAPROOT_CONTROL_NODE_SIZE * self.merkle_branch.len());
+        ret.push(self.leaf_version | self.output_key_parity as u8);
+        ret.extend_from_slice(&self.internal_key.serialize());
+        for node in &self.merkle_branch {
+            ret.extend_from_slice(node);
+        }
+        ret
+    }
+
+    /// Checks that `script` is committed to by `output_key` through this
+    /// control block. `secp` must be capable of verification.
+    pub fn verify_taproot_commitment(&self, secp: &Secp256k1, output_key: &TweakedPublicKey, script: &Script) -> bool {
+        let leaf = TapLeafHash::from_script(script, self.leaf_version);
+        let mut node = leaf.0;
+        for sibling in &self.merkle_branch {
+            node = TapBranchHash::from_node_hashes(&node, sibling).0;
+        }
+        match self.internal_key.tap_tweak(secp, Some(TapBranchHash(node))) {
+            Ok((key, parity)) => key == *output_key && parity == self.output_key_parity,
+            Err(_) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crypto::digest::Digest;
+    use crypto::sha2::Sha256;
+    use secp256k1::Secp256k1;
+    use serialize::hex::FromHex;
+
+    use util::key::XOnlyPublicKey;
+    use super::*;
+
+    #[test]
+    fn tag_constants() {
+        for &(tag, expected) in &[("TapLeaf", TAPLEAF_TAG), ("TapBranch", TAPBRANCH_TAG), ("TapTweak", TAPTWEAK_TAG)] {
+            let mut engine = Sha256::new();
+            engine.input(tag.as_bytes());
+            let mut hash = [0; 32];
+            engine.result(&mut hash);
+            assert_eq!(hash, expected);
+        }
+    }
+
+    #[test]
+    fn bip341_vectors() {
+        // BIP341 wallet test vectors, scriptPubKey cases 0 and 1
+        let secp = Secp256k1::without_caps();
+        let internal = XOnlyPublicKey::from_slice(&secp, &"d6889cb081036e0faefa3a35157ad71086b123b2b144b649798b494c300a961d".from_hex().unwrap()).unwrap();
+        assert_eq!(&TapTweakHash::from_key_and_tweak(&internal, None)[..],
+                   &"b86e7be8f39bab32a6f2c0443abbc210f0edac0e2c53d501b36b64437d9c6c70".from_hex().unwrap()[..]);
+
+        let script = hex_script!("20d85a959b0290bf19bb89ed43c916be835475d013da4b362117393e25a48229b8ac");
+        let leaf = TapLeafHash::from_script(&script, TAPROOT_LEAF_TAPSCRIPT);
+        assert_eq!(&leaf[..], &"5b75adecf53548f3ec6ad7d78383bf84cc57b55a3127c72b9a2481752dd88b21".from_hex().unwrap()[..]);
+    }
+
+    #[test]
+    fn branch_hash() {
+        let a = "5b75adecf53548f3ec6ad7d78383bf84cc57b55a3127c72b9a2481752dd88b21".from_hex().unwrap();
+        let b: Vec<u8> = (0..32).collect();
+        let expected = "d425ad827d193be5bb0d129178fd8fc875cf25273b5abcd66d6061fc196aaca1".from_hex().unwrap();
+        assert_eq!(&TapBranchHash::from_node_hashes(&a, &b)[..], &expected[..]);
+        assert_eq!(TapBranchHash::from_node_hashes(&a, &b), TapBranchHash::from_node_hashes(&b, &a));
+    }
+
+    #[test]
+    fn builder_bip341_vectors() {
+        // BIP341 wallet test vectors, scriptPubKey cases 1 and 3
+        let secp = Secp256k1::new();
+        let internal = XOnlyPublicKey::from_slice(&secp, &"187791b6f712a8ea41c8ecdd0ee77fab3e85263b37e1ec18a3651926b3a6cf27".from_hex().unwrap()).unwrap();
+        let script = hex_script!("20d85a959b0290bf19bb89ed43c916be835475d013da4b362117393e25a48229b8ac");
+        let info = TaprootBuilder::new().add_leaf(0, script.clone()).unwrap().finalize(&secp, internal).unwrap();
+        assert_eq!(&info.merkle_root().unwrap()[..], &"5b75adecf53548f3ec6ad7d78383bf84cc57b55a3127c72b9a2481752dd88b21".from_hex().unwrap()[..]);
+        assert_eq!(&info.output_key().as_inner().serialize()[..], &"147c9c57132f6e7ecddba9800bb0c4449251c92a1e60371ee77557b6620f3ea3".from_hex().unwrap()[..]);
+        assert!(info.output_key_parity());
+        assert_eq!(info.internal_key(), internal);
+        assert_eq!(info.script_map()[&(script, TAPROOT_LEAF_TAPSCRIPT)], vec![Vec::<[u8; 32]>::new()]);
+
+        let internal = XOnlyPublicKey::from_slice(&secp, &"ee4fe085983462a184015d1f782d6a5f8b9c2b60130aff050ce221ecf3786592".from_hex().unwrap()).unwrap();
+        let script_a = hex_script!("20387671353e273264c495656e27e39ba899ea8fee3bb69fb2a680e22093447d48ac");
+        let script_b = hex_script!("06424950333431");
+        let info = TaprootBuilder::new()
+            .add_leaf(1, script_a.clone()).unwrap()
+            .add_leaf_with_ver(1, script_b.clone(), 0xfa).unwrap()
+            .finalize(&secp, internal).unwrap();
+        assert_eq!(&info.merkle_root().unwrap()[..], &"6c2dc106ab816b73f9d07e3cd1ef2c8c1256f519748e0813e4edd2405d277bef".from_hex().unwrap()[..]);
+        assert_eq!(&info.output_key().as_inner().serialize()[..], &"712447206d7a5238acc7ff53fbe94a3b64539ad291c7cdbc490b7577e4b17df5".from_hex().unwrap()[..]);
+        assert!(!info.output_key_parity());
+        // each leaf's path is its sibling's hash
+        let leaf_a = TapLeafHash::from_script(&script_a, TAPROOT_LEAF_TAPSCRIPT);
+        let leaf_b = TapLeafHash::from_script(&script_b, 0xfa);
+        assert_eq!(info.script_map()[&(script_a, TAPROOT_LEAF_TAPSCRIPT)], vec![vec![leaf_b.0]]);
+        assert_eq!(info.script_map()[&(script_b, 0xfa)], vec![vec![leaf_a.0]]);
+    }
+
+    #[test]
+    fn builder_tree_shape() {
+        let secp = Secp256k1::new();
+        let internal = XOnlyPublicKey::from_slice(&secp, &"ee4fe085983462a184015d1f782d6a5f8b9c2b60130aff050ce221ecf3786592".from_hex().unwrap()).unwrap();
+        let script = |n: u8| hex_script!(&format!("51{:02x}", 0x50 + n));
+
+        // a, (b, (c, d))
+        let builder = TaprootBuilder::new()
+            .add_leaf(1, script(1)).unwrap()
+            .add_leaf(2, script(2)).unwrap()
+            .add_leaf(3, script(3)).unwrap();
+        assert!(!builder.is_complete());
+        match builder.clone().finalize(&secp, internal) {
+            Err(Error::IncompleteTree) => {},
+            x => panic!("unexpected result {:?}", x),
+        }
+        let builder = builder.add_leaf(3, script(4)).unwrap();
+        assert!(builder.is_complete());
+        let info = builder.clone().finalize(&secp, internal).unwrap();
+        assert_eq!(info.script_map()[&(script(1), TAPROOT_LEAF_TAPSCRIPT)][0].len(), 1);
+        assert_eq!(info.script_map()[&(script(2), TAPROOT_LEAF_TAPSCRIPT)][0].len(), 2);
+        assert_eq!(info.script_map()[&(script(4), TAPROOT_LEAF_TAPSCRIPT)][0].len(), 3);
+        match builder.add_leaf(1, script(5)) {
+            Err(Error::OverCompleteTree) => {},
+            x => panic!("unexpected result {:?}", x),
+        }
+
+        // a deeper branch must be completed before going back up
+        match TaprootBuilder::new().add_leaf(2, script(1)).unwrap().add_leaf(1, script(2)) {
+            Err(Error::NodeNotInDfsOrder) => {},
+            x => panic!("unexpected result {:?}", x),
+        }
+        match TaprootBuilder::new().add_leaf(129, script(1)) {
+            Err(Error::InvalidMerkleTreeDepth(129)) => {},
+            x => panic!("unexpected result {:?}", x),
+        }
+        match TaprootBuilder::new().add_leaf_with_ver(0, script(1), 0xc1) {
+            Err(Error::InvalidLeafVersion(0xc1)) => {},
+            x => panic!("unexpected result {:?}", x),
+        }
+        match TaprootBuilder::new().finalize(&secp, internal) {
+            Err(Error::EmptyTree) => {},
+            x => panic!("unexpected result {:?}", x),
+        }
+    }
+
+    #[test]
+    fn control_block_bip341_vector() {
+        // BIP341 wallet test vectors, scriptPubKey case 3
+        let secp = Secp256k1::new();
+        let internal = XOnlyPublicKey::from_slice(&secp, &"ee4fe085983462a184015d1f782d6a5f8b9c2b60130aff050ce221ecf3786592".from_hex().unwrap()).unwrap();
+        let script_a = hex_script!("20387671353e273264c495656e27e39ba899ea8fee3bb69fb2a680e22093447d48ac");
+        let script_b = hex_script!("06424950333431");
+        let info = TaprootBuilder::new()
+            .add_leaf(1, script_a.clone()).unwrap()
+            .add_leaf_with_ver(1, script_b.clone(), 0xfa).unwrap()
+            .finalize(&secp, internal).unwrap();
+
+        let cb_a = info.control_block(&(script_a.clone(), TAPROOT_LEAF_TAPSCRIPT)).unwrap();
+        let cb_b = info.control_block(&(script_b.clone(), 0xfa)).unwrap();
+        let ser_a = "c0ee4fe085983462a184015d1f782d6a5f8b9c2b60130aff050ce221ecf3786592f224a923cd0021ab202ab139cc56802ddb92dcfc172b9212261a539df79a112a".from_hex().unwrap();
+        let ser_b = "faee4fe085983462a184015d1f782d6a5f8b9c2b60130aff050ce221ecf37865928ad69ec7cf41c2a4001fd1f738bf1e505ce2277acdcaa63fe4765192497f47a7".from_hex().unwrap();
+        assert_eq!(cb_a.serialize(), ser_a);
+        assert_eq!(cb_b.serialize(), ser_b);
+        assert_eq!(ControlBlock::from_slice(&secp, &ser_a).unwrap(), cb_a);
+        assert_eq!(ControlBlock::from_slice(&secp, &ser_b).unwrap(), cb_b);
+        assert!(info.control_block(&(script_b.clone(), TAPROOT_LEAF_TAPSCRIPT)).is_none());
+
+        let output_key = info.output_key();
+        assert!(cb_a.verify_taproot_commitment(&secp, &output_key, &script_a));
+        assert!(cb_b.verify_taproot_commitment(&secp, &output_key, &script_b));
+        assert!(!cb_a.verify_taproot_commitment(&secp, &output_key, &script_b));
+        let mut wrong_parity = cb_a.clone();
+        wrong_parity.output_key_parity = true;
+        assert!(!wrong_parity.verify_taproot_commitment(&secp, &output_key, &script_a));
+
+        match ControlBlock::from_slice(&secp, &ser_a[..64]) {
+            Err(Error::InvalidControlBlockSize(64)) => {},
+            x => panic!("unexpected result {:?}", x),
+        }
+        assert!(ControlBlock::from_slice(&secp, &[0xc0; 33 + 32 * 129]).is_err());
+    }
+}